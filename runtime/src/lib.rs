@@ -1,7 +1,12 @@
 //! The Substrate Node Template runtime. This can be compiled with `#[no_std]`, ready for Wasm.
+//!
+//! The `runtime-benchmarks` feature adds the benchmarks of the UTXO module, which
+//! use the unstable `test` crate and so only build on nightly Rust.
 
 #![cfg_attr(not(feature = "std"), no_std)]
 #![cfg_attr(not(feature = "std"), feature(alloc))]
+// Benchmarks rely on the nightly-only `test` crate.
+#![cfg_attr(feature = "runtime-benchmarks", feature(test))]
 // `construct_runtime!` does a lot of recursion and requires us to increase the limit to 256.
 #![recursion_limit = "256"]
 #![allow(deprecated)]
@@ -59,6 +64,9 @@ pub type BlockNumber = u64;
 /// Index of an account's extrinsic in the chain.
 pub type Nonce = u64;
 
+#[cfg(all(test, feature = "runtime-benchmarks"))]
+extern crate test;
+
 pub mod utxo;
 
 /// Opaque types. These are used by the CLI to instantiate machinery that don't need to know
@@ -196,6 +204,8 @@ impl sudo::Trait for Runtime {
 
 impl utxo::Trait for Runtime {
     type Event = Event;
+    type LeftoverSalt = ();
+    type AuthorityToPubkey = utxo::AuthorityKeyAsPubkey;
 }
 
 construct_runtime!(
@@ -233,6 +243,25 @@ pub type CheckedExtrinsic = generic::CheckedExtrinsic<AccountId, Nonce, Call>;
 /// Executive: handles dispatch to the various modules.
 pub type Executive = executive::Executive<Runtime, Block, Context, Balances, AllModules>;
 
+/// Pool validity of a UTXO transaction, with its inputs authorized by `proof` if given.
+///
+/// Since some referred UTXOs may not be found in the storage yet, the transaction
+/// is tagged as requiring those particular UTXOs and as providing all the outputs
+/// it creates.
+fn validate_utxo(transaction: &utxo::Transaction, proof: Option<&utxo::BatchProof>) -> TransactionValidity {
+    const INVALID_UTXO: i8 = -99;
+
+    match <utxo::Module<Runtime>>::validate_for_pool_by(transaction, proof, System::block_number()) {
+        Ok(valid) => valid.into(),
+
+        // Transaction verification failed
+        Err(e) => {
+            runtime_io::print(e);
+            TransactionValidity::Invalid(INVALID_UTXO)
+        }
+    }
+}
+
 // Implement our runtime API endpoints. This is just a bunch of proxying.
 impl_runtime_apis! {
     impl runtime_api::Core<Block> for Runtime {
@@ -284,69 +313,37 @@ impl_runtime_apis! {
     impl runtime_api::TaggedTransactionQueue<Block> for Runtime {
         fn validate_transaction(tx: <Block as BlockT>::Extrinsic) -> TransactionValidity {
             use support::IsSubType;
-            use runtime_primitives::{
-                traits::Hash,
-                transaction_validity::{TransactionLongevity, TransactionPriority, TransactionValidity},
-            };
 
             // Extrinsics representing UTXO transaction need some special handling
-            if let Some(&utxo::Call::execute(ref transaction)) = IsSubType::<utxo::Module<Runtime>>::is_aux_sub_type(&tx.function) {
-                // List of tags to require
-                let requires;
-
-                // Transaction priority to assign
-                let priority;
-
-                const INVALID_UTXO: i8 = -99;
-
-                match <utxo::Module<Runtime>>::check_transaction(&transaction) {
-                    // Transaction verification failed
-                    Err(e) => {
-                        runtime_io::print(e);
-                        return TransactionValidity::Invalid(INVALID_UTXO);
-                    }
-
-                    // Transaction is valid and verified
-                    Ok(utxo::CheckInfo::Totals {input, output}) => {
-                        // All input UTXOs were found, so we consider input conditions to be met
-                        requires = Vec::new();
-
-                        // Priority is based on a transaction fee that is equal to the leftover value
-                        let max_priority = utxo::Value::from(TransactionPriority::max_value());
-                        priority = max_priority.min(input - output) as TransactionPriority;
-                    }
-                    
-                    // Transaction is missing inputs
-                    Ok(utxo::CheckInfo::MissingInputs(missing)) => {
-                        // Since some referred UTXOs were not found in the storage yet,
-                        // we tag current transaction as requiring those particular UTXOs
-                        requires = missing
-                            .iter()         // copies itself into a new vec
-                            .map(|hash| hash.as_fixed_bytes().to_vec())
-                            .collect();
-
-                        // Transaction could not be validated at this point,
-                        // so we have no sane way to calculate the priority    
-                        priority = 0;
-                    }
-                }
+            let utxo_validity = match IsSubType::<utxo::Module<Runtime>>::is_aux_sub_type(&tx.function) {
+                Some(&utxo::Call::execute(ref transaction)) => return validate_utxo(transaction, None),
+                Some(&utxo::Call::execute_signed(ref transaction)) => validate_utxo(transaction, None),
+                Some(&utxo::Call::execute_batched(ref transaction, ref proof)) => validate_utxo(transaction, Some(proof)),
 
-                // Output tags this transaction provides
-                let provides = transaction.outputs
-                    .iter()
-                    .map(|output| BlakeTwo256::hash_of(output).as_fixed_bytes().to_vec())
-                    .collect();
-
-                return TransactionValidity::Valid {
-                    requires,
-                    provides,
-                    priority,
-                    longevity: TransactionLongevity::max_value(),
-                };
-            }
+                // Fall back to default logic for non UTXO extrinsics
+                _ => return Executive::validate_transaction(tx),
+            };
 
-            // Fall back to default logic for non UTXO::execute extrinsics
-            Executive::validate_transaction(tx)
+            // Signed UTXO extrinsics must pass the account checks as well. They
+            // are ranked by their leftover like unsigned ones, and wait for both
+            // the account nonce and the missing inputs they require.
+            match (Executive::validate_transaction(tx), utxo_validity) {
+                (
+                    TransactionValidity::Valid { mut requires, mut provides, longevity, .. },
+                    TransactionValidity::Valid {
+                        priority,
+                        requires: utxo_requires,
+                        provides: utxo_provides,
+                        longevity: utxo_longevity,
+                    },
+                ) => {
+                    requires.extend(utxo_requires);
+                    provides.extend(utxo_provides);
+                    TransactionValidity::Valid { priority, requires, provides, longevity: longevity.min(utxo_longevity) }
+                }
+                (TransactionValidity::Valid { .. }, invalid) => invalid,
+                (invalid, _) => invalid,
+            }
         }
     }
 
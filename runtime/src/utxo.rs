@@ -2,29 +2,84 @@
 use support::{
     decl_event, decl_module, decl_storage,
     dispatch::{Result, Vec},
-    ensure, StorageMap, StorageValue,
+    ensure, storage::unhashed, EnumerableStorageMap, StorageMap, StorageValue,
 };
 use primitives::{H256, H512};
 use rstd::collections::btree_map::BTreeMap;
-use runtime_primitives::traits::{As, BlakeTwo256, Hash};
-use system::{ensure_inherent, ensure_signed};
-use super::Consensus;
+use runtime_primitives::traits::{As, BlakeTwo256, CheckedSub, Convert, Hash, Saturating, Zero};
+use runtime_primitives::transaction_validity::{
+    TransactionLongevity, TransactionPriority, TransactionTag, TransactionValidity,
+};
+use system::{ensure_inherent, ensure_root, ensure_signed};
+use super::{AuthorityId, Consensus};
 use parity_codec::{Decode, Encode};
 use runtime_io::sr25519_verify;
 #[cfg(feature = "std")]
+use runtime_primitives::BuildStorage;
+#[cfg(feature = "std")]
 use serde_derive::{Deserialize, Serialize};
 
 pub trait Trait: system::Trait {
     type Event: From<Event> + Into<<Self as system::Trait>::Event>;
+
+    /// Salts of the outputs paying leftover shares. Use `()` for the default scheme.
+    type LeftoverSalt: LeftoverSaltProvider<Self::BlockNumber>;
+
+    /// Public key receiving the leftover shares of each authority.
+    /// `AuthorityKeyAsPubkey` reuses the authority key as is.
+    type AuthorityToPubkey: Convert<AuthorityId, H256>;
+}
+
+/// Pays leftover shares to the public key made of the same bytes as the authority key
+pub struct AuthorityKeyAsPubkey;
+
+impl Convert<AuthorityId, H256> for AuthorityKeyAsPubkey {
+    fn convert(authority: AuthorityId) -> H256 {
+        authority.0.into()
+    }
+}
+
+/// Source of the salts keeping leftover share outputs from colliding
+pub trait LeftoverSaltProvider<BlockNumber> {
+    /// Salt of the share of `value` of an asset paid to `authority` at block `block`.
+    /// Called once per share. Two shares getting the same output hash don't
+    /// overwrite each other, but the second one ends up salted by `block_salt`.
+    fn next_salt(block: BlockNumber, authority: &H256, asset_id: AssetId, value: Value) -> u64;
+}
+
+/// Salts derived as the first 8 bytes, little endian, of
+/// `blake2_256((block, authority, asset_id, value).encode())`. An authority
+/// receives at most one share of each asset per block, so its shares can't
+/// collide with each other, nor with one paid in another block. A treasury or
+/// fallback key that is an authority too may receive a second share of the
+/// same value in a block though. The block number is hashed in full, so
+/// unlike `block_salt` nothing is truncated.
+impl<BlockNumber: Encode> LeftoverSaltProvider<BlockNumber> for () {
+    fn next_salt(block: BlockNumber, authority: &H256, asset_id: AssetId, value: Value) -> u64 {
+        let digest = BlakeTwo256::hash_of(&(block, authority, asset_id, value));
+
+        let mut salt = [0u8; 8];
+        salt.copy_from_slice(&digest.as_bytes()[..8]);
+        u64::from_le_bytes(salt)
+    }
 }
 
 /// Representation of UTXO value
 pub type Value = u128;
 
+/// Identifier of a fungible asset tracked in the UTXO set
+pub type AssetId = u32;
+
+/// Asset of outputs created before multi-asset support, also paying the leftover
+pub const NATIVE_ASSET: AssetId = 0;
+
 /// Representation of UTXO value
 type Signature = H512;
 
 /// Single transaction to be dispatched
+///
+/// Inputs and outputs may be listed in any order. Wherever a transaction
+/// must be identified, as by `txid`, its `canonical_encode` is used instead.
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
 #[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Encode, Decode, Hash)]
 pub struct Transaction {
@@ -59,11 +114,197 @@ pub struct TransactionOutput {
     pub pubkey: H256,
 
     /// Unique (potentially random) value used to distinguish this
-	/// particular output from others addressed to the same public
-	/// key with the same value. Prevents potential replay attacks.
+    /// particular output from others addressed to the same public
+    /// key with the same value. Prevents potential replay attacks.
+    /// Transactions creating an output identical to an unspent one
+    /// are rejected with "output already exists".
+    pub salt: u64,
+
+    /// Additional condition restricting who may spend this output and when
+    pub condition: SpendCondition,
+
+    /// Asset the value is denominated in
+    pub asset_id: AssetId,
+
+    /// Block at which the output, if still unspent, is removed and
+    /// its value added to the leftover. Must be in the future when created.
+    pub expires_at: Option<u64>,
+}
+
+/// Spending condition attached to an output.
+///
+/// Block heights are expressed as plain numbers, just like `salt`.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Hash)]
+pub enum SpendCondition {
+    /// Output may be spent by its owner at any time
+    Unconditional,
+
+    /// Escrow output. Before `deadline` only the owner may spend it,
+    /// starting from `deadline` only `refund_to` may reclaim it.
+    Refundable { refund_to: H256, deadline: u64 },
+
+    /// Output with a backup key. The owner may spend it at any time, while
+    /// `backup` may too once the output has stayed unspent for `delay` blocks.
+    Recoverable { backup: H256, delay: u64 },
+
+    /// Output losing value as it ages. The owner may spend it at any time, but
+    /// for every full `period` blocks since its creation, `rate_bps` basis points
+    /// of its value can't be spent anymore and go to the leftover instead.
+    /// Fully decayed outputs may still be spent, for nothing. A zero period never decays.
+    Demurrage { rate_bps: u16, period: u64 },
+
+    /// Conditional payment. The owner may spend it only while the value reported
+    /// by trusted oracles under `key`, in `OracleValues`, equals `expected`.
+    Oracle { key: H256, expected: u128 },
+}
+
+impl Default for SpendCondition {
+    fn default() -> Self {
+        SpendCondition::Unconditional
+    }
+}
+
+/// What happens to the leftover when there are no authorities to share it
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[derive(PartialEq, Eq, Clone, Encode, Decode)]
+pub enum LeftoverPolicy {
+    /// Keep accumulating it until some authorities show up
+    Hold,
+
+    /// Destroy it
+    Burn,
+
+    /// Pay all of it to the given public key
+    Credit(H256),
+}
+
+impl Default for LeftoverPolicy {
+    fn default() -> Self {
+        LeftoverPolicy::Hold
+    }
+}
+
+/// Single signature authorizing every input of a transaction at once,
+/// in place of their own signatures
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[derive(PartialEq, Eq, Clone, Encode, Decode)]
+pub struct BatchProof {
+    /// Key which must be allowed to spend every input
+    pub signer: H256,
+
+    /// Merkle root of the hashes of the UTXOs authorized, as built by `batch_root`
+    pub root: H256,
+
+    /// Signature of `batch_payload(transaction, root)` by `signer`
+    pub signature: Signature,
+
+    /// Inclusion proof of each input in the tree, in input order,
+    /// as built by `batch_path`
+    pub paths: Vec<Vec<H256>>,
+}
+
+/// Order in which authorities are considered when sharing the leftover
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[derive(PartialEq, Eq, Clone, Copy, Encode, Decode)]
+pub enum AuthorityOrder {
+    /// As reported by the consensus module
+    AsReported,
+
+    /// Ascending by public key, whatever order consensus reports them in
+    ByKey,
+}
+
+impl Default for AuthorityOrder {
+    fn default() -> Self {
+        AuthorityOrder::AsReported
+    }
+}
+
+/// Part of the leftover paid to a treasury rather than to the authorities
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[derive(PartialEq, Eq, Clone, Encode, Decode)]
+pub struct LeftoverSplit {
+    /// Treasury part in basis points, i.e. 1/10000ths of the leftover
+    pub treasury_bps: u16,
+
+    /// Public key of the treasury
+    pub treasury: H256,
+}
+
+/// Output as encoded by version 0 of the storage layout, before spend
+/// conditions, assets and expiry were introduced
+#[derive(Encode, Decode)]
+pub struct TransactionOutputV0 {
+    pub value: Value,
+    pub pubkey: H256,
     pub salt: u64,
 }
 
+impl From<TransactionOutputV0> for TransactionOutput {
+    fn from(output: TransactionOutputV0) -> TransactionOutput {
+        TransactionOutput {
+            value: output.value,
+            pubkey: output.pubkey,
+            salt: output.salt,
+            ..Default::default()
+        }
+    }
+}
+
+/// Storage prefix of `UnspentOutputs`, as derived by `decl_storage`
+const UNSPENT_OUTPUTS_PREFIX: &[u8] = b"Utxo UnspentOutputs";
+
+/// Version of the storage layout written by this runtime.
+/// Bump it together with a new step in `on_runtime_upgrade`.
+pub const CURRENT_STORAGE_VERSION: u32 = 3;
+
+/// Default for `MaxLockDuration`: about a year of 10 second blocks
+const DEFAULT_MAX_LOCK_DURATION: u64 = 3_153_600;
+
+/// Default for `SignatureWindow`: blocks during which a used signature is remembered
+const DEFAULT_SIGNATURE_WINDOW: u64 = 600;
+
+/// Default for `MaxRecentSignatures`
+const DEFAULT_MAX_RECENT_SIGNATURES: u32 = 10_000;
+
+/// Default for `MultisigLifetime`: about a day of 10 second blocks
+const DEFAULT_MULTISIG_LIFETIME: u64 = 8_640;
+
+/// Default for `MaxPendingMultisig`
+const DEFAULT_MAX_PENDING_MULTISIG: u32 = 1_000;
+
+/// Default for `CreationHistory`: about a day of 10 second blocks
+const DEFAULT_CREATION_HISTORY: u64 = 8_640;
+
+/// Most inputs `select_exact` combines
+const MAX_EXACT_INPUTS: usize = 4;
+
+/// Largest encoded transaction `validate_for_pool` admits
+pub const MAX_POOL_TX_SIZE: usize = 64 * 1024;
+
+/// Most inputs of a transaction `validate_for_pool` admits
+pub const MAX_POOL_TX_INPUTS: usize = 1_024;
+
+/// Number of blocks a transaction admitted by `validate_for_pool` may stay
+/// in the pool before it has to be validated again
+pub const POOL_LONGEVITY: TransactionLongevity = 64;
+
+/// Number of salts `mint` tries before giving up on hash collisions
+const MINT_ATTEMPTS: u64 = 16;
+
+/// Number of UTXOs listed by `debug_dump`
+#[cfg(feature = "std")]
+const DEBUG_DUMP_SAMPLE: usize = 10;
+
+/// Human readable part of the addresses built by `pubkey_to_address`
+#[cfg(feature = "std")]
+pub const ADDRESS_HRP: &str = "utxo";
+
+/// Characters encoding the 5-bit groups of an address, as in bech32
+#[cfg(feature = "std")]
+const ADDRESS_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
 /// A UTXO can be locked indefinitely or until a certain block height
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Encode, Decode, Hash)]
@@ -74,24 +315,240 @@ pub enum LockStatus<BlockNumber> {
 
 decl_storage! {
     trait Store for Module<T: Trait> as Utxo {
+        /// Identifier of this launch of the chain, mixed into every spend signature
+        /// so that transactions signed for another launch are never valid here.
+        /// Every chain spec must set a launch hash of its own: chains left with
+        /// the all-zero default accept each other's signatures.
+        pub LaunchHash get(launch_hash) config(): H256;
+
         /// All valid unspent transaction outputs are stored in this map.
         /// Initial set of UTXO is populated from the list stored in genesis.
         UnspentOutputs build(|config: &GenesisConfig<T>| {
             config.initial_utxo
                 .iter()
                 .cloned()
-                .map(|u| (BlakeTwo256::hash_of(&u), u))
+                .map(|u| (<Module<T>>::output_hash(&u), u))
                 .collect::<Vec<_>>()
-        }): map H256 => Option<TransactionOutput>;
+        }): linked_map H256 => Option<TransactionOutput>;
+
+        /// Number of UTXOs listed in the genesis configuration
+        pub GenesisUtxoCount get(genesis_utxo_count) build(|config: &GenesisConfig<T>| {
+            config.initial_utxo.len() as u64
+        }): u64;
+
+        /// Number of outputs in the UTXO set. Genesis outputs sharing
+        /// a hash overwrite each other and are counted once.
+        pub UtxoCount get(utxo_count) build(|config: &GenesisConfig<T>| {
+            config.initial_utxo
+                .iter()
+                .map(|u| (<Module<T>>::output_hash(u), ()))
+                .collect::<BTreeMap<_, _>>()
+                .len() as u64
+        }): u64;
+
+        /// Size of the UTXO set above which `UtxoSetLarge` is deposited
+        pub UtxoSetWarnThreshold get(utxo_set_warn_threshold) config(): u64 = u64::max_value();
+
+        /// Whether the UTXO set was above `UtxoSetWarnThreshold` at the end of the
+        /// last block, so that `UtxoSetLarge` is only deposited once per crossing
+        UtxoSetWarned: bool;
+
+        /// Index of unspent outputs by the public key that owns them.
+        pub OwnedOutputs get(owned_outputs) build(|config: &GenesisConfig<T>| {
+            let mut owned = BTreeMap::<H256, Vec<H256>>::new();
+            for utxo in config.initial_utxo.iter() {
+                owned
+                    .entry(utxo.pubkey)
+                    .or_insert_with(Vec::new)
+                    .push(<Module<T>>::output_hash(utxo));
+            }
+            owned.into_iter().collect::<Vec<_>>()
+        }): map H256 => Vec<H256>;
 
+        /// Version of the storage layout. Fresh chains start at the current one,
+        /// older chains are migrated by `on_runtime_upgrade`.
+        pub StorageVersion get(storage_version) build(|_: &GenesisConfig<T>| {
+            CURRENT_STORAGE_VERSION
+        }): u32;
 
-        /// Total leftover value to be redistributed among authorities.
+        /// Total leftover value of each asset to be redistributed among authorities.
         /// It is accumulated during block execution and then drained
         /// on block finalization.
-        pub LeftoverTotal get(leftover_total): Value;
+        pub LeftoverTotals get(leftover_total): linked_map AssetId => Value;
+
+        /// Native leftover of runtimes before storage version 2.
+        /// Moved to `LeftoverTotals` on upgrade.
+        LeftoverTotal: Value;
+
+        /// Number of outputs ever created for a public key, if `track_spend_counts`
+        pub ReceivedCount get(received_count): map H256 => u64;
+
+        /// Number of outputs ever spent by a public key, if `track_spend_counts`
+        pub SpentCount get(spent_count): map H256 => u64;
+
+        /// Part of the leftover paid to a treasury, if any
+        pub TreasurySplit get(treasury_split) config(): Option<LeftoverSplit>;
+
+        /// Whether the leftover is redistributed on block finalization at all.
+        /// When unset, leftover totals only grow, unless paid out by the faucet.
+        pub RedistributeLeftover get(redistribute_leftover) config(): bool = true;
+
+        /// Part of the leftover of every transaction which is burnt rather than
+        /// redistributed, in basis points. Rounded down, in favour of the authorities.
+        pub BurnFraction get(burn_fraction) config(): u16;
+
+        /// Fate of the leftover while the authority set is empty
+        pub NoAuthorityPolicy get(no_authority_policy) config(): LeftoverPolicy;
+
+        /// Order of the authorities, which picks the one receiving leftover
+        /// too small to be shared. Remainders of even shares are carried over
+        /// to the next block rather than given to any authority.
+        pub LeftoverOrder get(leftover_order) config(): AuthorityOrder;
+
+        /// Native leftover left by the transactions of the current block,
+        /// before any of it is burnt. Saturating.
+        pub BlockLeftover get(block_dust): Value;
+
+        /// Index of the authority receiving the next leftover too small to be shared
+        pub LeftoverCursor get(leftover_cursor): u32;
+
+        /// Native leftover ever paid to each authority, saturating. Holds one
+        /// entry per public key which was an authority, so it stays small.
+        AuthorityRewards: map H256 => Value;
+
+        /// Whether authority shares of the native leftover accrue in `ClaimableRewards`
+        /// until claimed, rather than being paid out as new outputs every block
+        pub AccrueRewards get(accrue_rewards) config(): bool;
+
+        /// Native leftover shares kept for each authority until it calls `claim_rewards`.
+        /// Also holds shares whose output would have collided with an existing one.
+        pub ClaimableRewards get(claimable_rewards): map H256 => Value;
+
+        /// Whether transactions must spend exactly as much as they create, leaving
+        /// no leftover and so paying no fee to the authorities
+        pub StrictBalance get(strict_balance) config(): bool;
+
+        /// Smallest native leftover a transaction must leave as its fee.
+        /// Zero, the default, lets transactions through without any fee.
+        pub MinFee get(min_fee) config(): Value;
+
+        /// Whether an event is deposited for every transaction failing verification
+        pub EmitRejections get(emit_rejections) config(): bool;
+
+        /// Whether all inputs of a transaction must be owned by the same public key
+        pub SingleOwnerTx get(single_owner_tx) config(): bool;
+
+        /// INSECURE, for development chains only: whether inputs may be spent
+        /// without a valid signature. Ignored unless the runtime is built with
+        /// the `insecure-unsigned-spend` feature, see `allow_unsigned_spend`.
+        AllowUnsignedSpend config(allow_unsigned_spend): bool;
+
+        /// Largest value a single output may carry
+        pub MaxOutputValue get(max_output_value) config(): Value = Value::max_value();
+
+        /// Number of transactions executed in the current block.
+        /// Reset at the start of every block.
+        pub BlockTxCount get(block_tx_count): u32;
+
+        /// Maximum number of transactions executed in a single block.
+        /// Unbounded unless configured otherwise.
+        pub MaxTxPerBlock get(max_tx_per_block) config(): u32 = u32::max_value();
+
+        /// Transaction which created an output, by output hash. Records are kept
+        /// after the output is spent, so that the lineage of coins can be followed.
+        OutputOrigin: map H256 => Option<H256>;
+
+        /// Block at which each unspent output was created
+        pub CreatedAt get(created_at): map H256 => T::BlockNumber;
+
+        /// Hashes of the outputs created in each recent block, in order of creation,
+        /// whether they are still unspent or not
+        CreatedInBlock: map T::BlockNumber => Vec<H256>;
+
+        /// Number of blocks `CreatedInBlock` is kept for
+        pub CreationHistory get(creation_history) config(): T::BlockNumber =
+            <T::BlockNumber as As<u64>>::sa(DEFAULT_CREATION_HISTORY);
+
+        /// Outputs to be removed at the end of each block, see `TransactionOutput::expires_at`
+        ExpiringOutputs: map T::BlockNumber => Vec<H256>;
+
+        /// Public keys whose outputs may not be spent
+        pub FrozenAccounts get(frozen_accounts): map H256 => bool;
+
+        /// Values reported by trusted oracles, by key, for `SpendCondition::Oracle`
+        /// outputs to check at spend time. Set by root or by an oracle module.
+        pub OracleValues get(oracle_value): map H256 => Option<u128>;
+
+        /// Events deposited in the current block by topic, as indices into the
+        /// events of this module in `system::events()`, in deposit order. Lets
+        /// indexers find events concerning a public key or a transaction
+        /// without decoding them all.
+        pub EventTopics get(event_topics): map H256 => Vec<u32>;
+
+        /// Number of events this module deposited in the current block, which
+        /// is the index of the next one in `EventTopics`
+        BlockEventCount get(block_event_count): u32;
+
+        /// Topics used in the current block, so that `EventTopics` can be cleared
+        BlockTopics: Vec<H256>;
+
+        /// Number of blocks an output must have existed for before `lock_utxo` accepts it
+        pub LockGracePeriod get(lock_grace_period) config(): T::BlockNumber;
+
+        /// Longest time-lock `lock_utxo` accepts, in blocks from now
+        pub MaxLockDuration get(max_lock_duration) config(): T::BlockNumber =
+            <T::BlockNumber as As<u64>>::sa(DEFAULT_MAX_LOCK_DURATION);
+
+        /// Signatures of recently executed transactions, with the block they were used at.
+        /// A signature found here may not be used again.
+        pub RecentSignatures get(recent_signature): map Signature => Option<T::BlockNumber>;
+
+        /// Recent signatures in order of use: a queue running from
+        /// `RecentSignaturesStart` up to, excluding, `RecentSignaturesEnd`
+        RecentSignatureQueue: map u32 => Option<Signature>;
+        RecentSignaturesStart: u32;
+        RecentSignaturesEnd: u32;
+
+        /// Number of blocks a used signature is remembered for
+        pub SignatureWindow get(signature_window) config(): T::BlockNumber =
+            <T::BlockNumber as As<u64>>::sa(DEFAULT_SIGNATURE_WINDOW);
+
+        /// Most signatures remembered at once. The oldest are forgotten first.
+        pub MaxRecentSignatures get(max_recent_signatures) config(): u32 = DEFAULT_MAX_RECENT_SIGNATURES;
+
+        /// UTXOs their owner marked as reserved, to keep other sessions of
+        /// their wallet from spending them. Only binding if `RejectReserved` is set.
+        pub ReservedOutputs get(reserved_outputs): map H256 => bool;
+
+        /// Number of actions their owner authorized on each UTXO by signing an
+        /// `owner_payload`, which includes it so that no such signature is valid twice
+        pub OwnerNonce get(owner_nonce): map H256 => u64;
+
+        /// Whether transactions spending reserved UTXOs are rejected
+        pub RejectReserved get(reject_reserved) config(): bool = true;
+
+        /// Transactions waiting for the signatures of their input owners, by txid.
+        /// Signatures collected so far are kept in the inputs, missing ones are zero.
+        pub PendingMultisig get(pending_multisig): map H256 => Option<Transaction>;
+
+        /// Pending transactions dropped at the start of each block
+        PendingMultisigExpiry: map T::BlockNumber => Vec<H256>;
+
+        /// Number of blocks a pending transaction waits for its signatures
+        pub MultisigLifetime get(multisig_lifetime) config(): T::BlockNumber =
+            <T::BlockNumber as As<u64>>::sa(DEFAULT_MULTISIG_LIFETIME);
+
+        /// Number of entries in `PendingMultisig`
+        pub PendingMultisigCount get(pending_multisig_count): u32;
+
+        /// Most transactions `PendingMultisig` may hold at once
+        pub MaxPendingMultisig get(max_pending_multisig) config(): u32 = DEFAULT_MAX_PENDING_MULTISIG;
 
         /// All UTXO that are locked
         LockedOutputs: map H256 => Option<LockStatus<T::BlockNumber>>;
+
+        /// Time-locked UTXOs by the block their lock ends at, released at the start of it
+        LockExpiry: map T::BlockNumber => Vec<H256>;
     }
 
     add_extra_genesis {
@@ -101,558 +558,6281 @@ decl_storage! {
 
 decl_module! {
     pub struct Module<T: Trait> for enum Call where origin: T::Origin {
-        fn deposit_event() = default;
+        /// Handler called by the system on block initialization
+        fn on_initialize(n: T::BlockNumber) {
+            <BlockTxCount<T>>::kill();
+            <BlockEventCount<T>>::kill();
+            <BlockLeftover<T>>::kill();
+            for topic in <BlockTopics<T>>::take() {
+                <EventTopics<T>>::remove(topic);
+            }
+            for txid in <PendingMultisigExpiry<T>>::take(n) {
+                if <PendingMultisig<T>>::take(txid).is_some() {
+                    <PendingMultisigCount<T>>::mutate(|count| *count = count.saturating_sub(1));
+                }
+            }
+            Self::purge_expired_locks(n);
+            if let Some(oldest) = n.checked_sub(&Self::creation_history()) {
+                <CreatedInBlock<T>>::remove(oldest);
+            }
+
+            if Self::storage_version() < CURRENT_STORAGE_VERSION {
+                Self::on_runtime_upgrade();
+            }
 
-        /// Dispatch a single transaction and update UTXO set accordingly
+            // Nothing but genesis has touched the UTXO set before the first block
+            if n == <T::BlockNumber as As<u64>>::sa(1) {
+                if let Err(e) = Self::audit_genesis() {
+                    runtime_io::print(e);
+                    Self::deposit_event(Event::GenesisAuditFailed(
+                        Self::genesis_utxo_count(),
+                        Self::stored_utxo_count(),
+                    ));
+                }
+            }
+        }
+
+        /// Dispatch a single transaction and update UTXO set accordingly.
+        ///
+        /// Submitted unsigned: no account is involved, the transaction pool
+        /// validates it through `pool_priority` and `pool_tags`, and the input
+        /// signatures are the only authorization.
         pub fn execute(origin, transaction: Transaction) -> Result {
             ensure_inherent(origin)?;
+            Self::execute_transaction(&transaction)
+        }
 
-            // Verify the transaction
-            let leftover = match Self::check_transaction(&transaction)? {
-                CheckInfo::Totals{input, output} => input - output,
-                CheckInfo::MissingInputs(_) => return Err("Invalid transaction inputs")
-            };
+        /// Same as `execute`, but submitted as an extrinsic signed by an account.
+        ///
+        /// The account only relays the transaction and gains no rights over
+        /// its inputs, which are still authorized by their own signatures.
+        /// In exchange, the extrinsic is subject to the usual account checks
+        /// such as nonces and fees.
+        pub fn execute_signed(origin, transaction: Transaction) -> Result {
+            ensure_signed(origin)?;
+            Self::execute_transaction(&transaction)
+        }
+
+        /// Same as `execute_signed`, but with every input authorized at once by
+        /// `proof` rather than by its own signature, which is ignored.
+        ///
+        /// Verifies a single signature however many inputs are spent, which
+        /// makes large consolidations cheaper.
+        pub fn execute_batched(origin, transaction: Transaction, proof: BatchProof) -> Result {
+            ensure_signed(origin)?;
+            Self::execute_transaction_by(&transaction, Some(&proof))
+        }
 
-            // Update unspent outputs
-            Self::update_storage(&transaction, leftover)?;
+        /// Consolidate every unlocked native output of `pubkey` into a single
+        /// output paying their whole value to `destination`.
+        ///
+        /// `signatures` sign the transaction built by `sweep_transaction`, one for
+        /// each of its inputs, in order.
+        pub fn sweep(origin, pubkey: H256, destination: H256, signatures: Vec<Signature>) -> Result {
+            ensure_signed(origin)?;
 
-            // Emit event
-            Self::deposit_event(Event::TransactionExecuted(transaction));
+            let mut transaction = Self::sweep_transaction(&pubkey, destination)?;
+            ensure!(transaction.inputs.len() == signatures.len(), "one signature per swept output required");
+            for (input, signature) in transaction.inputs.iter_mut().zip(signatures.into_iter()) {
+                input.signature = signature;
+            }
 
-            Ok(())
+            Self::execute_transaction(&transaction)
         }
 
-        /// DANGEROUS! Adds specified output to the storage potentially overwriting existing one.
-        /// Does not perform enough checks. Must only be used for testing purposes.
-        pub fn mint(origin, value: Value, pubkey: H256) -> Result {
+        /// Give the whole value of a UTXO to the authorities, by adding it to
+        /// the leftover of its asset.
+        ///
+        /// The input signature must be made over `donate_payload` by a key allowed
+        /// to spend the UTXO, so that a spend signature seen in the transaction
+        /// pool can't be used to donate somebody else's UTXO.
+        pub fn donate(origin, input: TransactionInput) -> Result {
             ensure_signed(origin)?;
-            let salt:u64 = <system::Module<T>>::block_number().as_();
-            let utxo = TransactionOutput { value, pubkey, salt };
-            let hash = BlakeTwo256::hash_of(&utxo);
 
-            if !<UnspentOutputs<T>>::exists(hash) {
-                <UnspentOutputs<T>>::insert(hash, utxo);
-            } else {
-                runtime_io::print("cannot mint due to hash collision");
+            let now: u64 = <system::Module<T>>::block_number().as_();
+            let hash = input.parent_output;
+            let output = <UnspentOutputs<T>>::get(&hash).ok_or(InputFailure::Missing.message())?;
+            let signer = Self::authorizing_key(&hash, &output, &Self::donate_payload(&hash), &input.signature, now)
+                .ok_or(InputFailure::BadSignature.message())?;
+            ensure!(
+                !<RecentSignatures<T>>::exists(&input.signature),
+                InputFailure::ReplayedSignature.message()
+            );
+            Self::check_input(0, &input, &[], Some(&signer), now, false, &mut None)?;
+
+            let new_total = Self::leftover_total(output.asset_id)
+                .checked_add(output.value)
+                .ok_or("Leftover overflow")?;
+            <LeftoverTotals<T>>::insert(output.asset_id, new_total);
+
+            Self::remove_utxo(&input.parent_output);
+            if Self::track_spend_counts() {
+                <SpentCount<T>>::mutate(output.pubkey, |count| *count += 1);
             }
 
+            Self::remember_signature(input.signature);
+            Self::deposit_event(Event::Donated(output.asset_id, output.value));
+
             Ok(())
         }
 
-        /// Handler called by the system on block finalization
-        fn on_finalize() {
-            let auth:Vec<_> = Consensus::authorities().iter().map(|x| x.0.into() ).collect();
-            Self::spend_leftover(&auth);
+        /// DANGEROUS! Adds specified output to the storage out of thin air.
+        /// Does not perform enough checks. Must only be used for testing purposes.
+        pub fn mint(origin, value: Value, pubkey: H256) -> Result {
+            ensure_signed(origin)?;
+            Self::create_output(value, pubkey)?;
+            Ok(())
         }
-    }
-}
 
-decl_event!(
-    pub enum Event {
-        /// Transaction was executed successfully
-        TransactionExecuted(Transaction),
-    }
-);
+        /// Pay `amount` of native leftover to `to`, instead of sharing it among
+        /// the authorities. Recycles value on test networks without minting.
+        pub fn faucet(origin, to: H256, amount: Value) -> Result {
+            ensure_root(origin)?;
+            ensure!(amount != 0, "output value must be nonzero");
 
-/// Information collected during transaction verification
-pub enum CheckInfo<'a> {
-    /// Combined value of all inputs and outputs
-    Totals { input: Value, output: Value },
+            let available = Self::leftover_total(NATIVE_ASSET);
+            ensure!(available >= amount, "insufficient dust for faucet");
 
-    /// Some referred UTXOs were missing
-    MissingInputs(Vec<&'a H256>),
-}
+            Self::create_output(amount, to)?;
+            if available == amount {
+                <LeftoverTotals<T>>::remove(NATIVE_ASSET);
+            } else {
+                <LeftoverTotals<T>>::insert(NATIVE_ASSET, available - amount);
+            }
+            Self::deposit_event(Event::FaucetPaid(to, amount));
 
-/// Result of transaction verification
-pub type CheckResult<'a> = rstd::result::Result<CheckInfo<'a>, &'static str>;
+            Ok(())
+        }
 
-impl<T: Trait> Module<T> {
-    /// Check transaction for validity.
-    /// 
-    /// Ensures that:
-    /// - inputs and outputs are not empty
-    /// - all inputs match to existing, unspent and unlocked outputs
-    /// - each input is used exactly once
-    /// - each output is defined exactly once and has nonzero value
-    /// - total output value must not exceed total input value
-    /// - new outputs do not collide with existing ones
-    /// - sum of input and output values does not overflow
-    /// - provided signatures are valid
-    pub fn check_transaction(transaction: &Transaction) -> CheckResult<'_> {
-        ensure!(!transaction.inputs.is_empty(), "no inputs");
-        ensure!(!transaction.outputs.is_empty(), "no outputs");
+        /// Release a time-lock before its deadline, with the consent of the UTXO owner.
+        ///
+        /// Unlike `unlock_utxo`, this is authorized by the owner signing
+        /// `cancel_lock_payload(hash)`, so anyone may submit it.
+        pub fn cancel_lock(origin, hash: H256, owner_sig: Signature) -> Result {
+            ensure_signed(origin)?;
+            ensure!(<UnspentOutputs<T>>::exists(&hash), "utxo does not exist");
 
-        {
-            let input_set: BTreeMap<_, ()> =
-                transaction.inputs.iter().map(|input| (input, ())).collect();
+            match <LockedOutputs<T>>::get(&hash) {
+                Some(LockStatus::LockedUntil(_)) => {}
+                Some(LockStatus::Locked) => return Err("utxo is not time-locked"),
+                None => return Err("utxo is not locked"),
+            }
+            Self::check_owner_signature(&hash, &Self::cancel_lock_payload(&hash), &owner_sig)?;
 
-            ensure!(
-                input_set.len() == transaction.inputs.len(),
-                "each input must only be used once"
-            );
+            Self::use_owner_signature(&hash, owner_sig);
+            <LockedOutputs<T>>::remove(&hash);
+            Self::deposit_event(Event::OutputUnlocked(hash));
+
+            Ok(())
         }
 
-        {
-            let output_set: BTreeMap<_, ()> = transaction
-                .outputs
-                .iter()
-                .map(|output| (output, ()))
-                .collect();
+        /// Spend a UTXO to a new one of the same value, owner and condition,
+        /// time-locked until `new_until`. Lets vesting outputs be locked again
+        /// as they mature, without ever being released.
+        ///
+        /// The input signature must be made over `relock_payload`, which binds
+        /// `new_until`, by a key allowed to spend the UTXO. Indefinite locks
+        /// are left to `unlock_utxo`, and the new lock obeys `MaxLockDuration`.
+        /// The new UTXO counts as created when the spent one was.
+        pub fn relock(origin, input: TransactionInput, new_until: T::BlockNumber) -> Result {
+            ensure_signed(origin)?;
+            let now = <system::Module<T>>::block_number();
+            ensure!(new_until > now, "block number is in the past");
+            ensure!(new_until - now <= Self::max_lock_duration(), "lock duration too long");
 
+            let hash = input.parent_output;
+            let output = <UnspentOutputs<T>>::get(&hash).ok_or("utxo does not exist")?;
+            if let Some(LockStatus::Locked) = <LockedOutputs<T>>::get(&hash) {
+                return Err("utxo is not time-locked");
+            }
             ensure!(
-                output_set.len() == transaction.outputs.len(),
-                "each output must be defined only once"
+                !Self::reject_reserved() || !Self::reserved_outputs(&hash),
+                InputFailure::Reserved.message()
+            );
+            ensure!(!Self::frozen_accounts(&output.pubkey), InputFailure::Frozen.message());
+            ensure!(
+                !<RecentSignatures<T>>::exists(&input.signature),
+                InputFailure::ReplayedSignature.message()
+            );
+            ensure!(
+                Self::authorizing_key(&hash, &output, &Self::relock_payload(&hash, new_until), &input.signature, now.as_())
+                    .is_some(),
+                InputFailure::BadSignature.message()
             );
-        }
-
-        let mut total_input: Value = 0;
-        let mut missing_utxo = Vec::new();
-        for input in transaction.inputs.iter() {
-            // Fetch UTXO from the storage
-            if let Some(output) = <UnspentOutputs<T>>::get(&input.parent_output) {
-                ensure!(
-                    !<LockedOutputs<T>>::exists(&input.parent_output),
-                    "utxo is locked"
-                );
 
-                // Check uxto signature authorization
-                ensure!(
-                    sr25519_verify(
-                        input.signature.as_fixed_bytes(),
-                        input.parent_output.as_fixed_bytes(),
-                        &output.pubkey
-                    ),
-                    "signature must be valid"
-                );
+            // The spent output still exists, so the new one can't take its hash
+            let relocked = Self::insert_salted(output)?;
 
-                // Add the value to the input total
-                total_input = total_input.checked_add(output.value).ok_or("input value overflow")?;
+            // The new output keeps the age of the spent one, so that relocking
+            // restarts neither demurrage nor recovery delays
+            if <CreatedAt<T>>::exists(&hash) {
+                <CreatedAt<T>>::insert(relocked, Self::created_at(&hash));
             } else {
-                missing_utxo.push(&input.parent_output);
+                <CreatedAt<T>>::remove(relocked);
             }
+            Self::remove_utxo(&hash);
+            Self::lock_until(relocked, new_until);
+            Self::remember_signature(input.signature);
+            Self::deposit_event(Event::OutputRelocked(hash, relocked));
+
+            Ok(())
         }
 
-        let mut total_output: Value = 0;
-        for output in transaction.outputs.iter() {
-            ensure!(output.value != 0, "output value must be nonzero");
+        /// Mark a UTXO as reserved, with the consent of its owner.
+        ///
+        /// Authorized by the owner signing `reserve_payload(hash)`, so anyone may
+        /// submit it. Unlike locks, reservations are meant for wallets to coordinate
+        /// and are lifted by the owner alone, through `release`.
+        pub fn reserve(origin, hash: H256, owner_sig: Signature) -> Result {
+            ensure_signed(origin)?;
+            Self::check_owner_signature(&hash, &Self::reserve_payload(&hash), &owner_sig)?;
+            ensure!(!Self::reserved_outputs(&hash), "utxo is already reserved");
 
-            let hash = BlakeTwo256::hash_of(output);
-            ensure!(!<UnspentOutputs<T>>::exists(hash), "output already exists");
+            Self::use_owner_signature(&hash, owner_sig);
+            <ReservedOutputs<T>>::insert(&hash, true);
+            Self::deposit_event(Event::OutputReserved(hash));
 
-            total_output = total_output
-                .checked_add(output.value)
-                .ok_or("output value overflow")?;
+            Ok(())
         }
 
-        if missing_utxo.is_empty() {
+        /// Lift the reservation of a UTXO, with the consent of its owner
+        /// signing `release_payload(hash)`.
+        pub fn release(origin, hash: H256, owner_sig: Signature) -> Result {
+            ensure_signed(origin)?;
+            Self::check_owner_signature(&hash, &Self::release_payload(&hash), &owner_sig)?;
+            ensure!(Self::reserved_outputs(&hash), "utxo is not reserved");
+
+            Self::use_owner_signature(&hash, owner_sig);
+            <ReservedOutputs<T>>::remove(&hash);
+            Self::deposit_event(Event::OutputReleased(hash));
+
+            Ok(())
+        }
+
+        /// Pay out the leftover shares kept for an authority as a single new output.
+        ///
+        /// Authorized by the authority signing `claim_payload(pubkey)`, so anyone may
+        /// submit it. Claims only ever pay the authority, so signatures may be reused.
+        pub fn claim_rewards(origin, pubkey: H256, signature: Signature) -> Result {
+            ensure_signed(origin)?;
             ensure!(
-                total_input >= total_output,
-                "output value must not exceed input value"
+                sr25519_verify(signature.as_fixed_bytes(), &Self::claim_payload(&pubkey), &pubkey),
+                "signature must be valid"
             );
-            Ok(CheckInfo::Totals {
-                input: total_input,
-                output: total_input,
-            })
-        } else {
-            Ok(CheckInfo::MissingInputs(missing_utxo))
-        }
-    }
-	
-    /// Redistribute combined leftover value evenly among chain authorities
-    fn spend_leftover(authorities: &[H256]) {
-        let leftover = <LeftoverTotal<T>>::take();
-        let share_value: Value = leftover
-            .checked_div(authorities.len() as Value)
-            .ok_or("No authorities")
-            .unwrap();
-        if share_value == 0 { return }
 
-        let remainder = leftover
-            .checked_sub(share_value * authorities.len() as Value)
-            .ok_or("Sub underflow")
-            .unwrap();
-        <LeftoverTotal<T>>::put(remainder as Value);
+            let value = Self::claimable_rewards(&pubkey);
+            ensure!(value != 0, "no rewards to claim");
 
-        for authority in authorities {
-            let utxo = TransactionOutput {
-                value: share_value,
-                pubkey: *authority,
-                salt: <system::Module<T>>::block_number().as_(),
-            };
+            let hash = Self::create_output(value, pubkey)?;
+            <ClaimableRewards<T>>::remove(&pubkey);
+            Self::deposit_event(Event::RewardsClaimed(pubkey, hash, value));
 
-            let hash = BlakeTwo256::hash_of(&utxo);
+            Ok(())
+        }
 
-            if !<UnspentOutputs<T>>::exists(hash) {
-                <UnspentOutputs<T>>::insert(hash, utxo);
-                runtime_io::print("leftover share sent to");
-                runtime_io::print(hash.as_fixed_bytes() as &[u8]);
-            } else {
-                runtime_io::print("leftover share wasted due to hash collision");
+        /// Submit a transaction whose inputs have several owners, for them to sign
+        /// one after the other with `add_signature`.
+        ///
+        /// Every input must exist, and at least one of them must carry a valid
+        /// signature, so that only owners of the inputs may propose. Other
+        /// signatures are dropped. A transaction signed in full is executed at
+        /// once, otherwise it is forgotten if not fully signed within
+        /// `MultisigLifetime` blocks. At most `MaxPendingMultisig` wait at once.
+        pub fn propose_multisig(origin, transaction: Transaction) -> Result {
+            ensure_signed(origin)?;
+            ensure!(!transaction.inputs.is_empty(), "no inputs");
+
+            let txid = Self::txid(&transaction);
+            ensure!(!<PendingMultisig<T>>::exists(&txid), "transaction is already pending");
+
+            let now: u64 = <system::Module<T>>::block_number().as_();
+            let payload = Self::signing_payload(&transaction);
+            let mut pending = transaction;
+            pending.inputs.sort_by_key(|input| input.parent_output);
+            for input in pending.inputs.iter_mut() {
+                let output = <UnspentOutputs<T>>::get(&input.parent_output).ok_or(InputFailure::Missing.message())?;
+                if !Self::signature_authorizes(&payload, input, &output, now) {
+                    input.signature = Signature::default();
+                }
+            }
+            let unsigned = pending.inputs.iter().filter(|input| input.signature == Signature::default()).count();
+            ensure!(unsigned < pending.inputs.len(), InputFailure::BadSignature.message());
+            if unsigned == 0 {
+                return Self::execute_transaction(&pending);
+            }
+            ensure!(
+                Self::pending_multisig_count() < Self::max_pending_multisig(),
+                "too many pending transactions"
+            );
+
+            let expiry = <system::Module<T>>::block_number() + Self::multisig_lifetime();
+            <PendingMultisig<T>>::insert(&txid, pending);
+            <PendingMultisigCount<T>>::mutate(|count| *count += 1);
+            <PendingMultisigExpiry<T>>::mutate(expiry, |txids| txids.push(txid));
+            Self::deposit_event(Event::MultisigProposed(txid));
+
+            Ok(())
+        }
+
+        /// Add the signature of one input owner to a pending transaction.
+        ///
+        /// Every owner signs the same `signing_payload` of the transaction. The
+        /// signature fills the first unsigned input it authorizes, so owners of
+        /// several inputs submit it once per input. Once every input is signed,
+        /// the transaction is executed just like by `execute`.
+        pub fn add_signature(origin, txid: H256, signature: Signature) -> Result {
+            ensure_signed(origin)?;
+            let mut transaction = Self::pending_multisig(&txid).ok_or("no such pending transaction")?;
+
+            let now: u64 = <system::Module<T>>::block_number().as_();
+            let payload = Self::signing_payload(&transaction);
+            let input = transaction
+                .inputs
+                .iter_mut()
+                .filter(|input| input.signature == Signature::default())
+                .find(|input| match <UnspentOutputs<T>>::get(&input.parent_output) {
+                    Some(output) => {
+                        let signed = TransactionInput { parent_output: input.parent_output, signature };
+                        Self::signature_authorizes(&payload, &signed, &output, now)
+                    }
+                    None => false,
+                })
+                .ok_or(InputFailure::BadSignature.message())?;
+            input.signature = signature;
+
+            if transaction.inputs.iter().any(|input| input.signature == Signature::default()) {
+                <PendingMultisig<T>>::insert(&txid, transaction);
+                return Ok(());
+            }
+
+            // Left pending if execution fails, missing the last signature
+            Self::execute_transaction(&transaction)?;
+            <PendingMultisig<T>>::remove(&txid);
+            <PendingMultisigCount<T>>::mutate(|count| *count = count.saturating_sub(1));
+
+            Ok(())
+        }
+
+        /// Prevent all outputs owned by `pubkey` from being spent.
+        ///
+        /// Unlike locks, this applies to the key rather than to single UTXOs,
+        /// including those it receives later.
+        pub fn freeze_account(origin, pubkey: H256) -> Result {
+            ensure_root(origin)?;
+            ensure!(!Self::frozen_accounts(&pubkey), "account is already frozen");
+
+            <FrozenAccounts<T>>::insert(&pubkey, true);
+            Self::deposit_event(Event::AccountFrozen(pubkey));
+
+            Ok(())
+        }
+
+        /// Allow outputs owned by a previously frozen `pubkey` to be spent again
+        pub fn unfreeze_account(origin, pubkey: H256) -> Result {
+            ensure_root(origin)?;
+            ensure!(Self::frozen_accounts(&pubkey), "account is not frozen");
+
+            <FrozenAccounts<T>>::remove(&pubkey);
+            Self::deposit_event(Event::AccountUnfrozen(pubkey));
+
+            Ok(())
+        }
+
+        /// Report the value of an oracle `key`, or clear it with `None`
+        pub fn set_oracle_value(origin, key: H256, value: Option<u128>) -> Result {
+            ensure_root(origin)?;
+            match value {
+                Some(value) => <OracleValues<T>>::insert(&key, value),
+                None => <OracleValues<T>>::remove(&key),
+            }
+            Ok(())
+        }
+
+        /// Convert UTXOs stored by a version 0 runtime, as a plain map, into
+        /// the current UTXO set, then move on to version 1 once `last` is set.
+        ///
+        /// A plain map can't be enumerated on chain, so the hashes of the
+        /// version 0 UTXOs must be collected off chain, from the blocks which
+        /// created them, and submitted in batches. Hashes with no version 0
+        /// entry are skipped. Outputs keep the hash they were stored under.
+        pub fn migrate_outputs_v0(origin, hashes: Vec<H256>, last: bool) -> Result {
+            ensure_root(origin)?;
+            ensure!(Self::storage_version() == 0, "storage is not at version 0");
+
+            for hash in hashes {
+                Self::migrate_output_v0(hash)?;
+            }
+            if last {
+                <StorageVersion<T>>::put(1);
+            }
+
+            Ok(())
+        }
+
+        /// Handler called by the system on block finalization
+        fn on_finalize() {
+            Self::expire_outputs(<system::Module<T>>::block_number());
+            if Self::redistribute_leftover() {
+                Self::spend_leftover(&Self::authority_keys());
             }
+            Self::expire_recent_signatures(<system::Module<T>>::block_number());
+            Self::warn_if_utxo_set_large();
         }
     }
+}
+
+decl_event!(
+    pub enum Event {
+        /// Transaction was executed successfully:
+        /// txid, hashes of spent and created outputs, and native leftover value
+        TransactionExecuted(H256, Vec<H256>, Vec<H256>, Value),
 
-    /// Update storage to reflect changes made by transaction
-    fn update_storage(transaction: &Transaction, leftover: Value) -> Result {
-        // Calculate new leftover total
-        let new_total = <LeftoverTotal<T>>::get()
-            .checked_add(leftover)
-            .ok_or("Leftover overflow")?;
-        <LeftoverTotal<T>>::put(new_total);
+        /// Transaction failed verification: txid, code of the reason, see
+        /// `error_code`, and the reason itself. Only deposited if `EmitRejections` is set.
+        TransactionRejected(H256, u16, Vec<u8>),
 
-        // Storing updated leftover value
-        for input in &transaction.inputs {
-            <UnspentOutputs<T>>::remove(input.parent_output);
+        /// Output was created by a transaction: owner public key and output hash
+        OutputCreated(H256, H256),
+
+        /// Native leftover was paid out by the faucet: recipient and value
+        FaucetPaid(H256, Value),
+
+        /// Part of the leftover of a transaction was burnt: asset and value
+        LeftoverBurnt(AssetId, Value),
+
+        /// Value of the asset was donated to the authorities
+        Donated(AssetId, Value),
+
+        /// Lock placed on the UTXO was released
+        OutputUnlocked(H256),
+
+        /// UTXO expired unspent and its value went to the leftover: hash and value
+        OutputExpired(H256, Value),
+
+        /// UTXO was spent to a new, time-locked one: old and new hashes
+        OutputRelocked(H256, H256),
+
+        /// Genesis UTXO set does not match its configuration:
+        /// number of configured and of actually stored outputs
+        GenesisAuditFailed(u64, u64),
+
+        /// Outputs of the public key may no longer be spent
+        AccountFrozen(H256),
+
+        /// Outputs of the public key may be spent again
+        AccountUnfrozen(H256),
+
+        /// Transaction was proposed for its input owners to sign: txid
+        MultisigProposed(H256),
+
+        /// UTXO was reserved by its owner
+        OutputReserved(H256),
+
+        /// Reservation of the UTXO was lifted by its owner
+        OutputReleased(H256),
+
+        /// Leftover shares kept for an authority were paid out:
+        /// authority public key, hash of the new output and its value
+        RewardsClaimed(H256, H256, Value),
+
+        /// UTXO set grew above `UtxoSetWarnThreshold`: number of outputs.
+        /// Deposited again only after the set has shrunk back below it.
+        UtxoSetLarge(u64),
+    }
+);
+
+/// Information collected during transaction verification
+pub enum CheckInfo<'a> {
+    /// Combined value of all native asset inputs and outputs,
+    /// along with the leftover value of every spent asset
+    Totals { input: Value, output: Value, leftover: BTreeMap<AssetId, Value> },
+
+    /// Some referred UTXOs were missing
+    MissingInputs(Vec<&'a H256>),
+}
+
+/// Result of transaction verification
+pub type CheckResult<'a> = rstd::result::Result<CheckInfo<'a>, &'static str>;
+
+/// Everything the transaction pool needs to know about an admitted transaction
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(PartialEq, Eq, Clone)]
+pub struct ValidTransaction {
+    /// Priority among ready transactions, see `pool_priority`
+    pub priority: TransactionPriority,
+
+    /// Outputs which must be created before the transaction can be included
+    pub requires: Vec<TransactionTag>,
+
+    /// Outputs the transaction creates
+    pub provides: Vec<TransactionTag>,
+
+    /// Number of blocks the transaction may stay in the pool
+    pub longevity: TransactionLongevity,
+}
+
+impl From<ValidTransaction> for TransactionValidity {
+    fn from(valid: ValidTransaction) -> TransactionValidity {
+        TransactionValidity::Valid {
+            priority: valid.priority,
+            requires: valid.requires,
+            provides: valid.provides,
+            longevity: valid.longevity,
         }
+    }
+}
 
-        // Add new UTXO to be used by future transactions
-        for output in &transaction.outputs {
-            let hash = BlakeTwo256::hash_of(output);
-            <UnspentOutputs<T>>::insert(hash, output);
+/// Self-contained evidence that a transaction was valid at some block.
+///
+/// Carries everything needed to re-check the transaction without access to
+/// the chain state. It does not prove that the spent outputs were part of
+/// that state: storage proofs for their `UnspentOutputs` entries have to be
+/// obtained from a node separately.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(PartialEq, Eq, Clone, Encode, Decode)]
+pub struct ValidityProof {
+    /// Block at which the transaction was verified
+    pub block: u64,
+
+    /// Outputs spent by the transaction, in input order,
+    /// along with the block each of them was created at
+    pub spent: Vec<(TransactionOutput, u64)>,
+
+    /// Total native value of inputs
+    pub input: Value,
+
+    /// Total native value of outputs
+    pub output: Value,
+}
+
+/// Reason a single transaction input was rejected
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum InputFailure {
+    /// Referred UTXO is not in the set
+    Missing,
+    /// Referred UTXO is locked
+    Locked,
+    /// Referred UTXO is reserved by its owner
+    Reserved,
+    /// Owner of the referred UTXO is frozen
+    Frozen,
+    /// Signature was already used by a recent transaction
+    ReplayedSignature,
+    /// Referred UTXO has another owner than previous inputs
+    MixedOwner,
+    /// Signature was not made by a key allowed to spend the UTXO
+    BadSignature,
+}
+
+impl InputFailure {
+    /// Message reported by `check_transaction` and dispatch for this failure
+    pub fn message(&self) -> &'static str {
+        match self {
+            InputFailure::Missing => "missing input utxo",
+            InputFailure::Locked => "utxo is locked",
+            InputFailure::Reserved => "utxo is reserved",
+            InputFailure::Frozen => "account is frozen",
+            InputFailure::ReplayedSignature => "signature recently used",
+            InputFailure::MixedOwner => "mixed-owner inputs not allowed",
+            InputFailure::BadSignature => "signature must be valid",
         }
+    }
+}
 
-        Ok(())
+/// Failure of the input at `index` within its transaction
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct InputError {
+    pub index: usize,
+    pub reason: InputFailure,
+}
+
+impl From<InputError> for &'static str {
+    fn from(error: InputError) -> &'static str {
+        error.reason.message()
     }
+}
 
-    pub fn lock_utxo(hash: &H256, until: Option<T::BlockNumber>) -> Result {
-        ensure!(!<LockedOutputs<T>>::exists(hash), "utxo is already locked");
-        ensure!(<UnspentOutputs<T>>::exists(hash), "utxo does not exist");
+/// How a single transaction input is authorized, as reported by `explain_input`
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[derive(PartialEq, Eq, Clone, Encode, Decode)]
+pub struct SpendExplanation {
+    /// Condition attached to the referred output
+    pub condition: SpendCondition,
 
-        if let Some(until) = until {
-            ensure!(
-                until > <system::Module<T>>::block_number(),
-                "block number is in the past"
-            );
-            <LockedOutputs<T>>::insert(hash, LockStatus::LockedUntil(until));
-        } else {
-            <LockedOutputs<T>>::insert(hash, LockStatus::Locked);
+    /// Keys allowed to spend the referred output at the current block
+    pub allowed_keys: Vec<H256>,
+
+    /// Allowed key which made the input signature, if any did
+    pub signer: Option<H256>,
+
+    /// Lock on the referred output, if any
+    pub lock: Option<LockStatus<u64>>,
+
+    /// Whether the lock still prevents spending at the current block
+    pub locked: bool,
+}
+
+/// Code `error_code` gives errors it doesn't know
+pub const UNKNOWN_ERROR_CODE: u16 = 0;
+
+/// Stable numeric codes of the errors reported by this module, for clients
+/// which shouldn't parse messages. Codes are never renumbered or reused:
+/// new errors are appended with the next free code.
+pub const ERROR_CODES: &[(&str, u16)] = &[
+    ("no inputs", 1),
+    ("no outputs", 2),
+    ("each input must only be used once", 3),
+    ("input references own output", 4),
+    ("each output must be defined only once", 5),
+    ("missing input utxo", 6),
+    ("utxo is locked", 7),
+    ("utxo is reserved", 8),
+    ("account is frozen", 9),
+    ("signature recently used", 10),
+    ("mixed-owner inputs not allowed", 11),
+    ("signature must be valid", 12),
+    ("output value must be nonzero", 13),
+    ("output value exceeds maximum", 14),
+    ("duplicate output across transactions", 15),
+    ("output already exists", 16),
+    ("input value overflow", 17),
+    ("output value overflow", 18),
+    ("output value must not exceed input value", 19),
+    ("dust not permitted in strict mode", 20),
+    ("Invalid transaction inputs", 21),
+    ("block transaction limit reached", 22),
+    ("Leftover overflow", 23),
+    ("input not included in batch", 24),
+    ("one inclusion proof per input required", 25),
+    ("proof does not match inputs", 26),
+    ("proof totals do not match", 27),
+    ("utxo spent twice in block", 28),
+    ("leftover value overflow", 29),
+    ("utxo does not exist", 30),
+    ("utxo is already locked", 31),
+    ("utxo is not locked", 32),
+    ("utxo is not time-locked", 33),
+    ("utxo too new to lock", 34),
+    ("block number is in the past", 35),
+    ("lock duration too long", 36),
+    ("utxo is already reserved", 37),
+    ("utxo is not reserved", 38),
+    ("account is already frozen", 39),
+    ("account is not frozen", 40),
+    ("cannot mint due to hash collision", 41),
+    ("insufficient dust for faucet", 42),
+    ("no spendable outputs to sweep", 43),
+    ("one signature per swept output required", 44),
+    ("transaction is already pending", 45),
+    ("no such pending transaction", 46),
+    ("no rewards to claim", 47),
+    ("value not conserved in block", 48),
+    ("output expiry must be in the future", 49),
+    ("fee below minimum", 50),
+    ("no such input", 51),
+    ("invalid address", 52),
+    ("address checksum mismatch", 53),
+    ("genesis utxo count mismatch", 54),
+    ("storage is not at version 0", 55),
+    ("invalid version 0 output", 56),
+    ("transaction too large", 57),
+    ("too many inputs", 58),
+    ("too many pending transactions", 59),
+];
+
+impl<T: Trait> Module<T> {
+    /// Verify a transaction and apply it to the UTXO set
+    fn execute_transaction(transaction: &Transaction) -> Result {
+        Self::execute_transaction_by(transaction, None)
+    }
+
+    /// Verify a transaction whose inputs are authorized by their own signatures
+    /// or, if given, by a batch proof, and apply it to the UTXO set
+    fn execute_transaction_by(transaction: &Transaction, batch: Option<&BatchProof>) -> Result {
+        ensure!(
+            Self::block_tx_count() < Self::max_tx_per_block(),
+            "block transaction limit reached"
+        );
+
+        // Verify the transaction
+        let signer = match batch {
+            Some(proof) => match Self::check_batch_proof(transaction, proof) {
+                Ok(()) => Some(&proof.signer),
+                Err(e) => return Self::reject(transaction, e),
+            },
+            None => None,
+        };
+        let now = <system::Module<T>>::block_number().as_();
+        let leftover = match Self::check_transaction_by(transaction, signer, now) {
+            Ok(CheckInfo::Totals { leftover, .. }) => leftover,
+            Ok(CheckInfo::MissingInputs(_)) => return Self::reject(transaction, "Invalid transaction inputs"),
+            Err(e) => return Self::reject(transaction, e),
+        };
+
+        // Update unspent outputs
+        let txid = Self::txid(transaction);
+        Self::update_storage(transaction, txid, &leftover)?;
+        match batch {
+            Some(proof) => Self::remember_signature(proof.signature),
+            None => for input in transaction.inputs.iter() {
+                Self::remember_signature(input.signature);
+            },
         }
+        <BlockTxCount<T>>::mutate(|count| *count += 1);
+
+        // Emit event
+        Self::deposit_indexed_event(&[txid], Event::TransactionExecuted(
+            txid,
+            transaction.inputs.iter().map(|input| input.parent_output).collect(),
+            Self::planned_outputs(transaction),
+            leftover.get(&NATIVE_ASSET).cloned().unwrap_or(0),
+        ));
 
         Ok(())
     }
 
-    pub fn unlock_utxo(hash: &H256) -> Result {
-        ensure!(!<LockedOutputs<T>>::exists(hash), "utxo is not locked");
-        <LockedOutputs<T>>::remove(hash);
-        Ok(())
+    /// Fail with `reason`, announcing the rejection first if `EmitRejections` is set
+    fn reject(transaction: &Transaction, reason: &'static str) -> Result {
+        if Self::emit_rejections() {
+            let txid = Self::txid(transaction);
+            let rejected = Event::TransactionRejected(txid, Self::error_code(reason), reason.as_bytes().to_vec());
+            Self::deposit_indexed_event(&[txid], rejected);
+        }
+        Err(reason)
     }
-}
 
-/// Tests for this module
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Stable code of an error reported by this module, as listed in `ERROR_CODES`,
+    /// or `UNKNOWN_ERROR_CODE` for errors it doesn't know, like those of other modules
+    pub fn error_code(err: &'static str) -> u16 {
+        ERROR_CODES
+            .iter()
+            .find(|(message, _)| *message == err)
+            .map(|(_, code)| *code)
+            .unwrap_or(UNKNOWN_ERROR_CODE)
+    }
 
-    use primitives::{Blake2Hasher, H256};
-    use runtime_io::with_externalities;
-    use runtime_primitives::{
-        testing::{Digest, DigestItem, Header},
-        traits::{BlakeTwo256, IdentityLookup},
-        BuildStorage,
-    };
-    use support::{assert_err, assert_ok, impl_outer_origin};
+    /// Unsigned transaction `sweep` executes at the current block.
+    ///
+    /// Spends the native outputs of `pubkey` as listed by `owned_outputs(pubkey)`,
+    /// skipping locked ones and, if they would be rejected, reserved ones. The
+    /// salt of the output is taken from the first swept UTXO, so the transaction
+    /// stays the same from one block to the next unless the swept outputs or
+    /// their demurrage change.
+    pub fn sweep_transaction(pubkey: &H256, destination: H256) -> rstd::result::Result<Transaction, &'static str> {
+        let swept = Self::sweepable_outputs(pubkey);
+        ensure!(!swept.is_empty(), "no spendable outputs to sweep");
 
-    impl_outer_origin! {
-        pub enum Origin for Test {}
+        let now: u64 = <system::Module<T>>::block_number().as_();
+        let mut total: Value = 0;
+        for (hash, output) in swept.iter() {
+            let spendable = output.value - Self::decay(hash, output, now);
+            total = total.checked_add(spendable).ok_or("input value overflow")?;
+        }
+
+        let mut salt = [0u8; 8];
+        salt.copy_from_slice(&swept[0].0.as_bytes()[..8]);
+        Ok(Transaction {
+            inputs: swept
+                .into_iter()
+                .map(|(parent_output, _)| TransactionInput { parent_output, signature: Signature::default() })
+                .collect(),
+            outputs: vec![TransactionOutput {
+                value: total,
+                pubkey: destination,
+                salt: u64::from_le_bytes(salt),
+                condition: SpendCondition::Unconditional,
+                asset_id: NATIVE_ASSET,
+                expires_at: None,
+            }],
+        })
     }
 
-    #[derive(Clone, Eq, PartialEq)]
-    pub struct Test;
-    impl system::Trait for Test {
-        type Origin = Origin;
-        type Index = u64;
-        type BlockNumber = u64;
-        type Hash = H256;
-        type Hashing = BlakeTwo256;
-        type Digest = Digest;
-        type AccountId = u64;
-        type Lookup = IdentityLookup<Self::AccountId>;
-        type Header = Header;
-        type Event = ();
-        type Log = DigestItem;
+    /// Native outputs of `pubkey` which are not locked, in owner index order
+    fn sweepable_outputs(pubkey: &H256) -> Vec<(H256, TransactionOutput)> {
+        let now = <system::Module<T>>::block_number();
+        Self::owned_outputs(pubkey)
+            .into_iter()
+            .filter(|hash| !Self::is_locked_at(hash, now))
+            .filter(|hash| !Self::reject_reserved() || !Self::reserved_outputs(hash))
+            .filter_map(|hash| <UnspentOutputs<T>>::get(&hash).map(|output| (hash, output)))
+            .filter(|(_, output)| output.asset_id == NATIVE_ASSET)
+            .collect()
     }
-    impl Trait for Test {
-        type Event = ();
+
+    /// Check transaction for validity.
+    /// 
+    /// Ensures that:
+    /// - inputs and outputs are not empty
+    /// - all inputs match to existing, unspent and unlocked outputs
+    ///   not owned by a frozen account. Time-locks apply up to their deadline,
+    ///   whether or not their record was purged yet
+    /// - each input is used exactly once
+    /// - no input refers to an output of the transaction itself
+    /// - no signature was used by a recently executed transaction
+    /// - all inputs share one owner, if `SingleOwnerTx` is set
+    /// - each output is defined exactly once and has nonzero value
+    ///   not exceeding `MaxOutputValue`, and expires in the future if ever
+    /// - total output value must not exceed total input value, for each asset
+    /// - native leftover is at least `MinFee`
+    /// - new outputs do not collide with existing ones
+    /// - sum of input and output values does not overflow
+    /// - provided signatures are valid and made by the key allowed
+    ///   to spend each output at the current block
+    pub fn check_transaction(transaction: &Transaction) -> CheckResult<'_> {
+        Self::check_transaction_by(transaction, None, <system::Module<T>>::block_number().as_())
     }
 
-    type Utxo = Module<Test>;
+    /// Same as `check_transaction`, but as of block `now`. If `signer` is given,
+    /// input signatures are ignored and `signer` must be allowed to spend every
+    /// input instead. It is up to the caller to make sure `signer` authorized the inputs.
+    fn check_transaction_by<'a>(transaction: &'a Transaction, signer: Option<&H256>, now: u64) -> CheckResult<'a> {
+        ensure!(!transaction.inputs.is_empty(), "no inputs");
+        ensure!(!transaction.outputs.is_empty(), "no outputs");
 
-    // Test set up
-    // Alice's Public Key: Pair::from_seed(*b"12345678901234567890123456789012");
-    const ALICE_KEY: [u8; 32] = [68, 169, 150, 190, 177, 238, 247, 189, 202, 185, 118, 171, 109, 44, 162, 97, 4, 131, 65, 100, 236, 242, 143, 179, 117, 96, 5, 118, 252, 198, 235, 15];
+        {
+            // Sorting and scanning neighbours is cheaper than building a set
+            // for transactions with many inputs. Signatures are ignored, so the
+            // same output can't be spent twice under different signatures.
+            let mut parents: Vec<_> = transaction.inputs.iter().map(|input| &input.parent_output).collect();
+            parents.sort_unstable();
 
-    // Alice's Signature to spend alice_utxo(): signs a token she owns Pair::sign(&message[..])
-    const ALICE_SIG: [u8; 64] = [220, 109, 218, 80, 85, 118, 140, 48, 193, 19, 77, 200, 60, 229, 91, 60, 70, 54, 54, 137, 154, 51, 201, 252, 98, 219, 172, 57, 1, 139, 86, 47, 162, 21, 50, 179, 196, 135, 167, 29, 171, 85, 3, 111, 46, 110, 10, 25, 239, 152, 176, 82, 114, 192, 125, 182, 240, 19, 192, 85, 227, 101, 148, 0]; //[148, 250, 180, 5, 112, 29, 240, 241, 122, 26, 249, 125, 87, 102, 180, 179, 127, 79, 120, 72, 253, 21, 26, 215, 157, 35, 208, 126, 54, 181, 150, 12, 117, 177, 134, 104, 124, 16, 70, 249, 31, 4, 131, 192, 247, 143, 73, 123, 24, 66, 144, 189, 64, 90, 65, 79, 185, 36, 107, 135, 195, 212, 219, 10];
+            ensure!(
+                parents.windows(2).all(|pair| pair[0] != pair[1]),
+                "each input must only be used once"
+            );
 
-    // Alice's Signature to spend alice_utxo_100(): signs a token she owns Pair::sign(&message[..])
-    const ALICE_SIG100: [u8; 64] = [212, 108, 199, 137, 228, 149, 233, 230, 129, 251, 80, 16, 160, 95, 191, 199, 207, 176, 151, 234, 5, 157, 245, 136, 62, 169, 87, 203, 188, 11, 47, 76, 230, 159, 10, 125, 35, 244, 76, 89, 174, 52, 41, 78, 32, 102, 200, 231, 31, 22, 35, 42, 143, 85, 255, 235, 31, 58, 236, 95, 52, 205, 224, 2]; // [228, 33, 239, 151, 136, 93, 241, 82, 205, 248, 154, 139, 52, 157, 231, 222, 66, 242, 86, 120, 92, 170, 98, 214, 78, 226, 93, 229, 130, 174, 168, 26, 7, 151, 88, 13, 185, 161, 15, 247, 222, 85, 235, 107, 246, 135, 23, 47, 162, 71, 81, 29, 227, 230, 210, 112, 0, 157, 86, 218, 130, 11, 8, 0];
+            // Outputs only exist once the transaction is applied
+            for output in transaction.outputs.iter() {
+                ensure!(
+                    parents.binary_search(&&Self::output_hash(output)).is_err(),
+                    "input references own output"
+                );
+            }
+        }
 
-    // Creates a max value UTXO for Alice
-    fn alice_utxo() -> (H256, TransactionOutput) {
-        let transaction = TransactionOutput {
-            value: Value::max_value(),
-            pubkey: H256::from_slice(&ALICE_KEY),
-            salt: 0,
-        };
+        {
+            let output_set: BTreeMap<_, ()> = transaction
+                .outputs
+                .iter()
+                .map(|output| (output, ()))
+                .collect();
 
-        (BlakeTwo256::hash_of(&transaction), transaction)
+            ensure!(
+                output_set.len() == transaction.outputs.len(),
+                "each output must be defined only once"
+            );
+        }
+
+        let single_owner = Self::single_owner_tx();
+        let mut owner: Option<H256> = None;
+        let mut total_input = BTreeMap::<AssetId, Value>::new();
+        let mut decayed = BTreeMap::<AssetId, Value>::new();
+        let mut missing_utxo = Vec::new();
+        let payload = Self::signing_payload(transaction);
+        // Each signature covers the whole transaction but is checked against the
+        // output its own input refers to, never against the keys owning any input
+        for (index, input) in transaction.inputs.iter().enumerate() {
+            match Self::check_input(index, input, &payload, signer, now, single_owner, &mut owner)? {
+                // Add the value to the input total of its asset
+                Some(output) => {
+                    let total = total_input.entry(output.asset_id).or_insert(0);
+                    *total = total.checked_add(output.value).ok_or("input value overflow")?;
+
+                    // Decayed value only counts towards the leftover
+                    let decay = Self::decay(&input.parent_output, &output, now);
+                    if decay != 0 {
+                        *decayed.entry(output.asset_id).or_insert(0) += decay;
+                    }
+                }
+                None => missing_utxo.push(&input.parent_output),
+            }
+        }
+
+        let max_output_value = Self::max_output_value();
+        let mut total_output = BTreeMap::<AssetId, Value>::new();
+        for output in transaction.outputs.iter() {
+            ensure!(output.value != 0, "output value must be nonzero");
+            ensure!(output.value <= max_output_value, "output value exceeds maximum");
+            ensure!(output.expires_at.map_or(true, |at| at > now), "output expiry must be in the future");
+
+            let hash = Self::output_hash(output);
+            if <UnspentOutputs<T>>::exists(hash) {
+                // `CreatedAt` tells outputs of earlier transactions in this block apart
+                let created_now = <CreatedAt<T>>::exists(hash) && Self::created_at(hash).as_() == now;
+                ensure!(!created_now, "duplicate output across transactions");
+                return Err("output already exists");
+            }
+
+            let total = total_output.entry(output.asset_id).or_insert(0);
+            *total = total.checked_add(output.value).ok_or("output value overflow")?;
+        }
+
+        if missing_utxo.is_empty() {
+            // Every asset is balanced on its own
+            for (asset_id, output) in total_output.iter() {
+                let spendable = total_input.get(asset_id).cloned().unwrap_or(0)
+                    - decayed.get(asset_id).cloned().unwrap_or(0);
+                ensure!(spendable >= *output, "output value must not exceed input value");
+            }
+            let leftover: BTreeMap<_, _> = total_input
+                .iter()
+                .map(|(asset_id, input)| (*asset_id, input - total_output.get(asset_id).cloned().unwrap_or(0)))
+                .collect();
+            ensure!(
+                !Self::strict_balance() || leftover.values().all(|value| *value == 0),
+                "dust not permitted in strict mode"
+            );
+            ensure!(
+                leftover.get(&NATIVE_ASSET).cloned().unwrap_or(0) >= Self::min_fee(),
+                "fee below minimum"
+            );
+            Ok(CheckInfo::Totals {
+                input: total_input.get(&NATIVE_ASSET).cloned().unwrap_or(0),
+                output: total_output.get(&NATIVE_ASSET).cloned().unwrap_or(0),
+                leftover,
+            })
+        } else {
+            Ok(CheckInfo::MissingInputs(missing_utxo))
+        }
     }
 
-    // Creates a 100 value UTXO for Alice
-    fn alice_utxo_100() -> (H256, TransactionOutput) {
-        let transaction = TransactionOutput {
-            value: 100,
-            pubkey: H256::from_slice(&ALICE_KEY),
-            salt: 0,
-        };
+    /// Check several unrelated transactions, as a pool snapshot, without changing state.
+    ///
+    /// Each transaction is checked on its own against the current UTXO set, so
+    /// one spending an output created by another reports it as missing, and
+    /// transactions spending the same output may all be found valid.
+    pub fn verify_many(transactions: &[Transaction]) -> Vec<CheckResult<'_>> {
+        transactions.iter().map(Self::check_transaction).collect()
+    }
+
+    /// Outputs spent by a transaction, in the order of its inputs.
+    ///
+    /// Read-only. Fails if any input refers to an output which is not in
+    /// the UTXO set; the hash of the first such input is printed.
+    pub fn resolve_inputs(transaction: &Transaction) -> rstd::result::Result<Vec<TransactionOutput>, &'static str> {
+        transaction
+            .inputs
+            .iter()
+            .map(|input| {
+                <UnspentOutputs<T>>::get(&input.parent_output).ok_or_else(|| {
+                    let message = InputFailure::Missing.message();
+                    runtime_io::print(message);
+                    runtime_io::print(input.parent_output.as_fixed_bytes() as &[u8]);
+                    message
+                })
+            })
+            .collect()
+    }
+
+    /// Check whether a transaction only moves value between outputs of a single owner.
+    ///
+    /// True when every input spends an output of the same pubkey every output
+    /// pays to. Read-only and unverified: transactions with no inputs or outputs,
+    /// or spending outputs not in the UTXO set, are never self-transfers.
+    pub fn is_self_transfer(transaction: &Transaction) -> bool {
+        let owner = match transaction.outputs.first() {
+            Some(output) => output.pubkey,
+            None => return false,
+        };
+
+        !transaction.inputs.is_empty()
+            && transaction.outputs.iter().all(|output| output.pubkey == owner)
+            && transaction.inputs.iter().all(|input| {
+                <UnspentOutputs<T>>::get(&input.parent_output).map_or(false, |spent| spent.pubkey == owner)
+            })
+    }
+
+    /// Identifier of a transaction, computed over its canonical encoding so
+    /// that it doesn't change when signatures are malleated or reordered.
+    pub fn txid(transaction: &Transaction) -> H256 {
+        BlakeTwo256::hash(&Self::canonical_encode(transaction))
+    }
+
+    /// Hashes of the outputs a transaction creates, in order.
+    ///
+    /// Pure: neither verifies the transaction nor reads storage, so wallets
+    /// can chain on outputs before submitting the transaction creating them.
+    pub fn planned_outputs(transaction: &Transaction) -> Vec<H256> {
+        transaction.outputs.iter().map(Self::output_hash).collect()
+    }
+
+    /// Id of the transaction which created the output, spent or not.
+    /// `None` for outputs not created by a transaction, such as genesis ones.
+    pub fn origin_of(hash: &H256) -> Option<H256> {
+        <OutputOrigin<T>>::get(hash)
+    }
+
+    /// Message every input owner signs to authorize a transaction: the launch
+    /// hash of the chain followed by the canonical encoding of the transaction.
+    ///
+    /// Signatures thus cover every input and output, so they can't be lifted
+    /// into another transaction, while the ordering of inputs and outputs and
+    /// the signatures themselves don't change what has to be signed.
+    pub fn signing_payload(transaction: &Transaction) -> Vec<u8> {
+        let mut payload = Self::launch_hash().as_bytes().to_vec();
+        payload.extend_from_slice(&Self::canonical_encode(transaction));
+        payload
+    }
+
+    /// Message signed by the signer of a `BatchProof`: the signing payload of
+    /// the transaction followed by the root of the batched UTXOs
+    pub fn batch_payload(transaction: &Transaction, root: &H256) -> Vec<u8> {
+        let mut payload = Self::signing_payload(transaction);
+        payload.extend_from_slice(root.as_bytes());
+        payload
+    }
+
+    /// Check that the batch proof was signed by its signer and covers every input
+    fn check_batch_proof(transaction: &Transaction, proof: &BatchProof) -> Result {
+        ensure!(proof.paths.len() == transaction.inputs.len(), "one inclusion proof per input required");
+        ensure!(
+            !<RecentSignatures<T>>::exists(&proof.signature),
+            InputFailure::ReplayedSignature.message()
+        );
+        ensure!(
+            sr25519_verify(proof.signature.as_fixed_bytes(), &Self::batch_payload(transaction, &proof.root), &proof.signer),
+            InputFailure::BadSignature.message()
+        );
+
+        for (input, path) in transaction.inputs.iter().zip(proof.paths.iter()) {
+            let root = path.iter().fold(input.parent_output, |node, sibling| Self::batch_node(&node, sibling));
+            ensure!(root == proof.root, "input not included in batch");
+        }
+
+        Ok(())
+    }
+
+    /// Merkle root of UTXO hashes, as signed in a `BatchProof`.
+    ///
+    /// Each node hashes its two children in ascending order, so that paths
+    /// need not say on which side siblings are. A node left without sibling
+    /// is carried up to the next level unchanged.
+    pub fn batch_root(leaves: &[H256]) -> H256 {
+        let mut level = leaves.to_vec();
+        while level.len() > 1 {
+            level = Self::batch_level(&level);
+        }
+        level.first().cloned().unwrap_or_default()
+    }
+
+    /// Inclusion proof of `leaves[index]` in `batch_root(leaves)`:
+    /// the siblings met on the way up to the root
+    pub fn batch_path(leaves: &[H256], mut index: usize) -> Vec<H256> {
+        let mut path = Vec::new();
+        let mut level = leaves.to_vec();
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(index ^ 1) {
+                path.push(*sibling);
+            }
+            level = Self::batch_level(&level);
+            index /= 2;
+        }
+        path
+    }
+
+    /// Next level up of a batch Merkle tree
+    fn batch_level(level: &[H256]) -> Vec<H256> {
+        level
+            .chunks(2)
+            .map(|pair| if pair.len() == 2 { Self::batch_node(&pair[0], &pair[1]) } else { pair[0] })
+            .collect()
+    }
+
+    /// Parent of two nodes of a batch Merkle tree
+    fn batch_node(a: &H256, b: &H256) -> H256 {
+        let (low, high) = if a < b { (a, b) } else { (b, a) };
+        BlakeTwo256::hash_of(&(low, high))
+    }
+
+    /// Identifier of an output in the UTXO set.
+    ///
+    /// This is the one canonical way to compute a UTXO hash; everything
+    /// inserting into or looking up the set goes through it.
+    pub fn output_hash(output: &TransactionOutput) -> H256 {
+        BlakeTwo256::hash_of(output)
+    }
+
+    /// Check the input at `index` against the UTXO it refers to.
+    ///
+    /// Returns the referred output, or `None` if it is not in the UTXO set.
+    /// The input signature must be made over `payload`, the signing payload of
+    /// its transaction, unless a batch `signer` is given.
+    /// `owner` carries the owner of previous inputs across calls.
+    fn check_input(
+        index: usize,
+        input: &TransactionInput,
+        payload: &[u8],
+        signer: Option<&H256>,
+        now: u64,
+        single_owner: bool,
+        owner: &mut Option<H256>,
+    ) -> rstd::result::Result<Option<TransactionOutput>, InputError> {
+        let fail = |reason| InputError { index, reason };
+
+        // Unsigned inputs all carry the same placeholder signature
+        let unsigned = signer.is_none() && Self::allow_unsigned_spend();
+        if signer.is_none() && !unsigned && <RecentSignatures<T>>::exists(&input.signature) {
+            return Err(fail(InputFailure::ReplayedSignature));
+        }
+
+        // Fetch UTXO from the storage
+        let output = match <UnspentOutputs<T>>::get(&input.parent_output) {
+            Some(output) => output,
+            None => return Ok(None),
+        };
+
+        if Self::is_locked_at(&input.parent_output, <T::BlockNumber as As<u64>>::sa(now)) {
+            return Err(fail(InputFailure::Locked));
+        }
+        if Self::reject_reserved() && Self::reserved_outputs(&input.parent_output) {
+            return Err(fail(InputFailure::Reserved));
+        }
+        if Self::frozen_accounts(&output.pubkey) {
+            return Err(fail(InputFailure::Frozen));
+        }
+        if single_owner && *owner.get_or_insert(output.pubkey) != output.pubkey {
+            return Err(fail(InputFailure::MixedOwner));
+        }
+
+        // Check uxto signature authorization
+        let authorized = match signer {
+            Some(signer) => Self::spending_keys(&input.parent_output, &output, now).contains(&signer),
+            None if unsigned => true,
+            None => Self::signature_authorizes(payload, input, &output, now),
+        };
+        if !authorized {
+            return Err(fail(InputFailure::BadSignature));
+        }
+
+        Ok(Some(output))
+    }
+
+    /// Whether the signature of `input` was made over the transaction signing
+    /// `payload` by a key allowed to spend `output`, the UTXO it refers to, at block `now`.
+    /// The only check of spend signatures, shared by every verification path.
+    fn signature_authorizes(payload: &[u8], input: &TransactionInput, output: &TransactionOutput, now: u64) -> bool {
+        Self::authorizing_key(&input.parent_output, output, payload, &input.signature, now).is_some()
+    }
+
+    /// Key allowed to spend `output`, stored under `hash`, at block `now`
+    /// which made `signature` over `payload`, if any
+    fn authorizing_key(
+        hash: &H256,
+        output: &TransactionOutput,
+        payload: &[u8],
+        signature: &Signature,
+        now: u64,
+    ) -> Option<H256> {
+        Self::spending_keys(hash, output, now)
+            .into_iter()
+            .find(|key| sr25519_verify(signature.as_fixed_bytes(), payload, key))
+            .cloned()
+    }
+
+    /// Check the signature of every input against the whole transaction, and nothing else.
+    ///
+    /// Performs the same signature check as `check_transaction`, over
+    /// `signing_payload(transaction)`, apart from values, locks and other rules,
+    /// and reports the first input whose signature is invalid. Inputs referring
+    /// to outputs which are not in the UTXO set can't be checked and are skipped.
+    pub fn verify_transaction_signatures(transaction: &Transaction) -> rstd::result::Result<(), InputError> {
+        let now: u64 = <system::Module<T>>::block_number().as_();
+        let payload = Self::signing_payload(transaction);
+
+        for (index, input) in transaction.inputs.iter().enumerate() {
+            if let Some(output) = <UnspentOutputs<T>>::get(&input.parent_output) {
+                if !Self::signature_authorizes(&payload, input, &output, now) {
+                    return Err(InputError { index, reason: InputFailure::BadSignature });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find the first input of a transaction which can't be spent, and why.
+    ///
+    /// Stops at the first failure. Unlike `check_transaction`, missing inputs
+    /// are reported as a failure too. Returns `None` if every input is spendable.
+    pub fn first_input_error(transaction: &Transaction) -> Option<InputError> {
+        let now: u64 = <system::Module<T>>::block_number().as_();
+        let single_owner = Self::single_owner_tx();
+        let mut owner = None;
+        let payload = Self::signing_payload(transaction);
+
+        for (index, input) in transaction.inputs.iter().enumerate() {
+            match Self::check_input(index, input, &payload, None, now, single_owner, &mut owner) {
+                Ok(Some(_)) => {}
+                Ok(None) => return Some(InputError { index, reason: InputFailure::Missing }),
+                Err(error) => return Some(error),
+            }
+        }
+
+        None
+    }
+
+    /// Describe how the input at `index` is, or would be, authorized at the current block.
+    ///
+    /// Read-only, for wallets debugging spends of conditional outputs. Reports
+    /// the keys the condition of the referred output allows, which of them
+    /// signed the input and whether a lock applies, but checks nothing else.
+    pub fn explain_input(transaction: &Transaction, index: usize) -> rstd::result::Result<SpendExplanation, &'static str> {
+        let input = transaction.inputs.get(index).ok_or("no such input")?;
+        let output = <UnspentOutputs<T>>::get(&input.parent_output).ok_or(InputFailure::Missing.message())?;
+        let now = <system::Module<T>>::block_number();
+
+        let payload = Self::signing_payload(transaction);
+        let allowed_keys: Vec<H256> = Self::spending_keys(&input.parent_output, &output, now.as_())
+            .into_iter()
+            .cloned()
+            .collect();
+        let signer = allowed_keys
+            .iter()
+            .find(|key| sr25519_verify(input.signature.as_fixed_bytes(), &payload, key))
+            .cloned();
+        let lock = <LockedOutputs<T>>::get(&input.parent_output).map(|lock| match lock {
+            LockStatus::Locked => LockStatus::Locked,
+            LockStatus::LockedUntil(until) => LockStatus::LockedUntil(until.as_()),
+        });
+
+        Ok(SpendExplanation {
+            condition: output.condition,
+            allowed_keys,
+            signer,
+            lock,
+            locked: Self::is_locked_at(&input.parent_output, now),
+        })
+    }
+
+    /// Public keys which are allowed to spend the output stored under `hash` at block `now`
+    fn spending_keys<'a>(hash: &H256, output: &'a TransactionOutput, now: u64) -> Vec<&'a H256> {
+        // Only recoverable outputs depend on their age, and oracle outputs
+        // can't be spent at all until the expected value is reported
+        let created_at = match output.condition {
+            SpendCondition::Recoverable { .. } => Self::created_at(hash).as_(),
+            SpendCondition::Oracle { ref key, expected } if Self::oracle_value(key) != Some(expected) => {
+                return Vec::new();
+            }
+            _ => 0,
+        };
+        Self::keys_allowed_at(output, created_at, now)
+    }
+
+    /// Public keys allowed to spend an output created at block `created_at`, at block `now`.
+    /// Stateless counterpart of `spending_keys`, so oracle conditions are taken as met.
+    fn keys_allowed_at(output: &TransactionOutput, created_at: u64, now: u64) -> Vec<&H256> {
+        match output.condition {
+            SpendCondition::Unconditional => vec![&output.pubkey],
+            SpendCondition::Refundable { ref refund_to, deadline } => {
+                if now < deadline { vec![&output.pubkey] } else { vec![refund_to] }
+            }
+            SpendCondition::Recoverable { ref backup, delay } => {
+                if now.saturating_sub(created_at) >= delay {
+                    vec![&output.pubkey, backup]
+                } else {
+                    vec![&output.pubkey]
+                }
+            }
+            SpendCondition::Demurrage { .. } => vec![&output.pubkey],
+            SpendCondition::Oracle { .. } => vec![&output.pubkey],
+        }
+    }
+
+    /// Value lost by the output stored under `hash` to demurrage at block `now`
+    fn decay(hash: &H256, output: &TransactionOutput, now: u64) -> Value {
+        match output.condition {
+            SpendCondition::Demurrage { .. } => Self::decay_at(output, Self::created_at(hash).as_(), now),
+            _ => 0,
+        }
+    }
+
+    /// Value lost to demurrage by an output created at block `created_at`, at block `now`
+    fn decay_at(output: &TransactionOutput, created_at: u64, now: u64) -> Value {
+        match output.condition {
+            SpendCondition::Demurrage { rate_bps, period } if period != 0 => {
+                let periods = now.saturating_sub(created_at) / period;
+                let bps = u64::from(rate_bps).saturating_mul(periods).min(10_000);
+                Self::bps_part(output.value, bps as u16)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Verify a transaction against the current state and bundle the
+    /// evidence of its validity, to be checked elsewhere by `check_validity_proof`.
+    pub fn validity_proof(transaction: &Transaction) -> rstd::result::Result<ValidityProof, &'static str> {
+        let (input, output, _) = Self::value_flow(transaction)?;
+        let spent = Self::resolve_inputs(transaction)?
+            .into_iter()
+            .zip(transaction.inputs.iter())
+            .map(|(utxo, input)| (utxo, Self::created_at(&input.parent_output).as_()))
+            .collect();
+
+        Ok(ValidityProof {
+            block: <system::Module<T>>::block_number().as_(),
+            spent,
+            input,
+            output,
+        })
+    }
+
+    /// Check a transaction against a validity proof, without reading any state.
+    ///
+    /// Verifies that the proof covers exactly the outputs the inputs refer to,
+    /// that each input is signed by a key allowed to spend its output at the
+    /// proof block, and that every asset balances. Locks, frozen accounts, oracle
+    /// conditions and the existence of the spent outputs are not covered.
+    pub fn check_validity_proof(transaction: &Transaction, proof: &ValidityProof) -> Result {
+        ensure!(!transaction.inputs.is_empty(), "no inputs");
+        ensure!(proof.spent.len() == transaction.inputs.len(), "proof does not match inputs");
+
+        let payload = Self::signing_payload(transaction);
+        let mut total_input = BTreeMap::<AssetId, Value>::new();
+        let mut decayed = BTreeMap::<AssetId, Value>::new();
+        for (input, (utxo, created_at)) in transaction.inputs.iter().zip(proof.spent.iter()) {
+            ensure!(Self::output_hash(utxo) == input.parent_output, "proof does not match inputs");
+            ensure!(
+                Self::keys_allowed_at(utxo, *created_at, proof.block)
+                    .iter()
+                    .any(|key| sr25519_verify(input.signature.as_fixed_bytes(), &payload, key)),
+                "signature must be valid"
+            );
+
+            let total = total_input.entry(utxo.asset_id).or_insert(0);
+            *total = total.checked_add(utxo.value).ok_or("input value overflow")?;
+            *decayed.entry(utxo.asset_id).or_insert(0) += Self::decay_at(utxo, *created_at, proof.block);
+        }
+
+        let mut total_output = BTreeMap::<AssetId, Value>::new();
+        for output in transaction.outputs.iter() {
+            let total = total_output.entry(output.asset_id).or_insert(0);
+            *total = total.checked_add(output.value).ok_or("output value overflow")?;
+        }
+
+        for (asset_id, output) in total_output.iter() {
+            let spendable = total_input.get(asset_id).cloned().unwrap_or(0)
+                - decayed.get(asset_id).cloned().unwrap_or(0);
+            ensure!(spendable >= *output, "output value must not exceed input value");
+        }
+        ensure!(
+            total_input.get(&NATIVE_ASSET).cloned().unwrap_or(0) == proof.input
+                && total_output.get(&NATIVE_ASSET).cloned().unwrap_or(0) == proof.output,
+            "proof totals do not match"
+        );
+
+        Ok(())
+    }
+
+    /// Native value flow of a verified transaction: `(input, output, leftover)`,
+    /// so that auditors can confirm `input == output + leftover` on their own.
+    ///
+    /// Fails if the transaction is invalid or some of its inputs are missing.
+    pub fn value_flow(transaction: &Transaction) -> rstd::result::Result<(Value, Value, Value), &'static str> {
+        match Self::check_transaction(transaction)? {
+            CheckInfo::Totals { input, output, .. } => Ok((input, output, input - output)),
+            CheckInfo::MissingInputs(_) => Err("Invalid transaction inputs"),
+        }
+    }
+
+    /// Native value flow of a proposed block: `(input, output, leftover)` summed
+    /// over its transactions, so that an author can confirm conservation of value
+    /// before sealing.
+    ///
+    /// Every transaction is checked against the current UTXO set with `value_flow`,
+    /// so the block may not spend outputs it creates itself. Fails if any of the
+    /// transactions is invalid, or if two of them spend the same output.
+    pub fn block_value_summary(
+        transactions: &[Transaction],
+    ) -> rstd::result::Result<(Value, Value, Value), &'static str> {
+        let mut spent = BTreeMap::new();
+        let (mut total_input, mut total_output, mut total_leftover) = (0 as Value, 0 as Value, 0 as Value);
+
+        for transaction in transactions {
+            for input in transaction.inputs.iter() {
+                ensure!(spent.insert(input.parent_output, ()).is_none(), "utxo spent twice in block");
+            }
+
+            let (input, output, leftover) = Self::value_flow(transaction)?;
+            total_input = total_input.checked_add(input).ok_or("input value overflow")?;
+            total_output = total_output.checked_add(output).ok_or("output value overflow")?;
+            total_leftover = total_leftover.checked_add(leftover).ok_or("leftover value overflow")?;
+        }
+
+        Ok((total_input, total_output, total_leftover))
+    }
+
+    /// Check that a block conserves native value: its transactions spend exactly
+    /// as much as they create plus `leftover_distributed`, the leftover paid out
+    /// or burnt on its behalf. Subject to the same limits as `block_value_summary`.
+    pub fn verify_block_conservation(transactions: &[Transaction], leftover_distributed: Value) -> Result {
+        let (input, output, _) = Self::block_value_summary(transactions)?;
+        ensure!(
+            output.checked_add(leftover_distributed) == Some(input),
+            "value not conserved in block"
+        );
+        Ok(())
+    }
+
+    /// Compute the fee rate of a transaction, i.e. leftover value per encoded byte.
+    ///
+    /// Returns `None` if the transaction cannot be fully verified against
+    /// the current UTXO set, including when some of its inputs are missing.
+    pub fn fee_rate(transaction: &Transaction) -> Option<Value> {
+        match Self::check_transaction(transaction) {
+            Ok(CheckInfo::Totals { input, output, .. }) => Self::per_byte(transaction, input - output),
+            _ => None,
+        }
+    }
+
+    /// Verify a transaction and rank it for inclusion by the transaction pool.
+    ///
+    /// Priority grows with the fee and shrinks with the encoded size, clamped to `u64`.
+    /// Transactions still waiting for some of their inputs get the lowest priority,
+    /// while invalid ones return the verification error so the pool can drop them.
+    pub fn pool_priority(transaction: &Transaction) -> rstd::result::Result<u64, &'static str> {
+        Ok(Self::rank(transaction, Self::check_transaction(transaction)?))
+    }
+
+    /// Pool priority of a transaction given the outcome of its verification
+    fn rank(transaction: &Transaction, info: CheckInfo) -> u64 {
+        match info {
+            CheckInfo::Totals { input, output, .. } => {
+                let rate = Self::per_byte(transaction, input - output).unwrap_or(0);
+                rate.min(Value::from(u64::max_value())) as u64
+            }
+            CheckInfo::MissingInputs(_) => 0,
+        }
+    }
+
+    /// Decide whether the transaction pool should admit a transaction.
+    ///
+    /// Rejects transactions spending more than `MAX_POOL_TX_INPUTS` inputs or
+    /// larger than `MAX_POOL_TX_SIZE`, then runs every check of `check_transaction`
+    /// as of block `now`, signatures, locks, reservations, frozen owners and value
+    /// limits included, and returns the priority and tags the pool needs.
+    /// Transactions waiting for some of their inputs are admitted, requiring
+    /// the missing outputs, and every admitted one is only valid for `POOL_LONGEVITY`.
+    pub fn validate_for_pool(
+        transaction: &Transaction,
+        now: T::BlockNumber,
+    ) -> rstd::result::Result<ValidTransaction, &'static str> {
+        Self::validate_for_pool_by(transaction, None, now)
+    }
+
+    /// Same as `validate_for_pool`, but with the inputs authorized by `batch`,
+    /// if given, as for `execute_batched`
+    pub fn validate_for_pool_by(
+        transaction: &Transaction,
+        batch: Option<&BatchProof>,
+        now: T::BlockNumber,
+    ) -> rstd::result::Result<ValidTransaction, &'static str> {
+        ensure!(transaction.inputs.len() <= MAX_POOL_TX_INPUTS, "too many inputs");
+        ensure!(transaction.encode().len() <= MAX_POOL_TX_SIZE, "transaction too large");
+
+        let signer = match batch {
+            Some(proof) => {
+                Self::check_batch_proof(transaction, proof)?;
+                Some(&proof.signer)
+            }
+            None => None,
+        };
+        let info = Self::check_transaction_by(transaction, signer, now.as_())?;
+        let (requires, provides) = Self::pool_tags(transaction);
+
+        Ok(ValidTransaction {
+            priority: Self::rank(transaction, info),
+            requires,
+            provides,
+            longevity: POOL_LONGEVITY,
+        })
+    }
+
+    /// Tags used by the transaction pool to order dependent transactions.
+    ///
+    /// Returns `(requires, provides)`. A transaction requires those of its inputs
+    /// which are not in the UTXO set yet and provides every output it creates, so
+    /// that a child spending a pending output is queued behind its parent.
+    pub fn pool_tags(transaction: &Transaction) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        let requires = transaction
+            .inputs
+            .iter()
+            .filter(|input| !<UnspentOutputs<T>>::exists(input.parent_output))
+            .map(|input| input.parent_output.as_fixed_bytes().to_vec())
+            .collect();
+
+        let provides = transaction
+            .outputs
+            .iter()
+            .map(|output| Self::output_hash(output).as_fixed_bytes().to_vec())
+            .collect();
+
+        (requires, provides)
+    }
+
+    /// Check whether two transactions have the same economic effect.
+    ///
+    /// Transactions are equivalent when they spend the same inputs and create the
+    /// same outputs. Signatures and ordering are ignored, so malleated duplicates
+    /// of a transaction compare equal.
+    pub fn same_effect(a: &Transaction, b: &Transaction) -> bool {
+        Self::canonical_encode(a) == Self::canonical_encode(b)
+    }
+
+    /// Encoding of a transaction which doesn't depend on how it was put together.
+    ///
+    /// Encodes the normalized transaction with its outputs sorted too, so equal
+    /// transactions listing inputs and outputs in different orders encode alike.
+    /// Both the `txid` and the message input signatures cover, `signing_payload`,
+    /// are built from it, which is why signatures are left out.
+    pub fn canonical_encode(transaction: &Transaction) -> Vec<u8> {
+        let mut canonical = Self::normalize(transaction);
+        canonical.outputs.sort();
+        canonical.encode()
+    }
+
+    /// Canonical form of a transaction, immune to signature malleability.
+    ///
+    /// Inputs are sorted by the output they spend and every signature is zeroed.
+    /// The result is only meant for comparison and identification: it carries
+    /// no valid signatures and so can never be dispatched itself.
+    pub fn normalize(transaction: &Transaction) -> Transaction {
+        let mut normalized = transaction.clone();
+        normalized.inputs.sort_by_key(|input| input.parent_output);
+        for input in normalized.inputs.iter_mut() {
+            input.signature = Signature::default();
+        }
+        normalized
+    }
+
+    /// Spread the leftover value of a transaction over its encoded size
+    fn per_byte(transaction: &Transaction, leftover: Value) -> Option<Value> {
+        leftover.checked_div(transaction.encode().len() as Value)
+    }
+	
+    /// Redistribute combined leftover value evenly among chain authorities,
+    /// each asset separately
+    fn spend_leftover(authorities: &[H256]) {
+        let mut authorities = authorities.to_vec();
+        if Self::leftover_order() == AuthorityOrder::ByKey {
+            authorities.sort();
+        }
+
+        let assets: Vec<_> = <LeftoverTotals<T>>::enumerate().map(|(asset_id, _)| asset_id).collect();
+        for asset_id in assets {
+            Self::spend_asset_leftover(&authorities, asset_id);
+        }
+    }
+
+    /// Redistribute the leftover value of one asset
+    fn spend_asset_leftover(authorities: &[H256], asset_id: AssetId) {
+        let leftover = <LeftoverTotals<T>>::take(asset_id);
+        let spent = Self::distribute_leftover(authorities, asset_id, leftover);
+
+        // Whatever was not paid out or burnt must have been written back, in debug builds
+        debug_assert_eq!(
+            spent.checked_add(Self::leftover_total(asset_id)),
+            Some(leftover),
+            "leftover not conserved"
+        );
+    }
+
+    /// Pay out `leftover` drained from the total of an asset, writing back
+    /// what is kept for later. Returns the value paid out or burnt.
+    fn distribute_leftover(authorities: &[H256], asset_id: AssetId, leftover: Value) -> Value {
+        if authorities.is_empty() {
+            if leftover == 0 {
+                return 0;
+            }
+            return match Self::no_authority_policy() {
+                LeftoverPolicy::Hold => {
+                    <LeftoverTotals<T>>::insert(asset_id, leftover);
+                    0
+                }
+                LeftoverPolicy::Burn => {
+                    runtime_io::print("leftover burnt for lack of authorities");
+                    leftover
+                }
+                LeftoverPolicy::Credit(pubkey) => leftover - Self::send_leftover_share(pubkey, asset_id, leftover),
+            };
+        }
+
+        // Value of shares which found no free output and went back to the leftover
+        let mut kept: Value = 0;
+        let treasury_part = match Self::treasury_split() {
+            Some(split) => {
+                let treasury_part = Self::bps_part(leftover, split.treasury_bps);
+                if treasury_part != 0 {
+                    kept += Self::send_leftover_share(split.treasury, asset_id, treasury_part);
+                }
+                treasury_part
+            }
+            None => 0,
+        };
+        let leftover = leftover - treasury_part;
+
+        let share_value: Value = leftover
+            .checked_div(authorities.len() as Value)
+            .ok_or("No authorities")
+            .unwrap();
+
+        if share_value == 0 {
+            // Leftover is too small to be shared evenly. Rather than stalling,
+            // hand all of it to a single authority picked round-robin.
+            if leftover != 0 {
+                let cursor = Self::leftover_cursor();
+                <LeftoverCursor<T>>::put(cursor.wrapping_add(1));
+                kept += Self::reward_authority(authorities[cursor as usize % authorities.len()], asset_id, leftover);
+            }
+            return treasury_part + leftover - kept
+        }
+
+        let remainder = leftover
+            .checked_sub(share_value * authorities.len() as Value)
+            .ok_or("Sub underflow")
+            .unwrap();
+        if remainder != 0 {
+            <LeftoverTotals<T>>::mutate(asset_id, |total| *total = total.saturating_add(remainder));
+        }
+
+        for authority in authorities {
+            kept += Self::reward_authority(*authority, asset_id, share_value);
+        }
+
+        treasury_part + leftover - remainder - kept
+    }
+
+    /// Pay a share of the leftover to an authority, keeping track of its native rewards.
+    ///
+    /// Native shares are kept in `ClaimableRewards` if `AccrueRewards` is set,
+    /// or if their output would overwrite an existing one. Other assets have
+    /// no claimable balance, so their shares get a salt of their own instead.
+    /// Returns the value kept in the leftover, as `insert_leftover_share`.
+    fn reward_authority(authority: H256, asset_id: AssetId, value: Value) -> Value {
+        if asset_id == NATIVE_ASSET {
+            <AuthorityRewards<T>>::mutate(&authority, |total| *total = total.saturating_add(value));
+        }
+
+        let (hash, utxo) = Self::leftover_share(authority, asset_id, value);
+        if asset_id == NATIVE_ASSET && (Self::accrue_rewards() || <UnspentOutputs<T>>::exists(hash)) {
+            <ClaimableRewards<T>>::mutate(&authority, |claimable| *claimable = claimable.saturating_add(value));
+            return 0;
+        }
+        Self::insert_leftover_share(hash, utxo)
+    }
+
+    /// Whether any of the `dust`, the leftover of a transaction, would be paid out
+    /// on finalization with `authority_count` authorities, rather than burnt or held.
+    ///
+    /// Amounts too small to be shared evenly still go to a single authority
+    /// in turn, so with authorities every part that isn't burnt is paid out.
+    pub fn dust_is_distributable(dust: Value, authority_count: usize) -> bool {
+        let kept = dust - Self::bps_part(dust, Self::burn_fraction());
+        if kept == 0 {
+            return false;
+        }
+        match Self::no_authority_policy() {
+            _ if authority_count != 0 => true,
+            LeftoverPolicy::Credit(_) => true,
+            LeftoverPolicy::Hold | LeftoverPolicy::Burn => false,
+        }
+    }
+
+    /// Public keys of the current authorities, which the leftover is shared among
+    fn authority_keys() -> Vec<H256> {
+        Consensus::authorities().into_iter().map(T::AuthorityToPubkey::convert).collect()
+    }
+
+    /// Whether `pubkey` is one of the current authorities
+    pub fn is_authority(pubkey: &H256) -> bool {
+        Self::authority_keys().contains(pubkey)
+    }
+
+    /// Total native leftover paid to `authority` as its share, since genesis,
+    /// including shares kept in `ClaimableRewards`.
+    /// Payments to a treasury or to the no-authority fallback don't count.
+    pub fn rewards_of(authority: &H256) -> Value {
+        <AuthorityRewards<T>>::get(authority)
+    }
+
+    /// Part of the leftover given in basis points, as for the treasury or
+    /// for burning. Rounded down, so that the authorities get the rest.
+    /// Basis points above 10000 count as 10000.
+    fn bps_part(leftover: Value, bps: u16) -> Value {
+        let bps = Value::from(bps.min(10_000));
+        // Split the multiplication so that it can't overflow
+        leftover / 10_000 * bps + leftover % 10_000 * bps / 10_000
+    }
+
+    /// Output paying a share of the leftover to an authority, along with its hash
+    fn leftover_share(authority: H256, asset_id: AssetId, value: Value) -> (H256, TransactionOutput) {
+        let utxo = TransactionOutput {
+            value,
+            pubkey: authority,
+            salt: Self::leftover_salt(&authority, asset_id, value),
+            condition: SpendCondition::Unconditional,
+            asset_id,
+            expires_at: None,
+        };
+
+        (Self::output_hash(&utxo), utxo)
+    }
+
+    /// Create an output paying a share of the leftover to an authority.
+    /// Returns the value kept in the leftover instead, as `insert_leftover_share`.
+    fn send_leftover_share(authority: H256, asset_id: AssetId, value: Value) -> Value {
+        let (hash, utxo) = Self::leftover_share(authority, asset_id, value);
+        Self::insert_leftover_share(hash, utxo)
+    }
+
+    /// Add a share output built by `leftover_share` to the UTXO set.
+    ///
+    /// Share salts are predictable, so an output may already exist at the hash
+    /// of a share, created on purpose or by an earlier share. Rather than
+    /// overwriting it, the share then gets a salt of its own from `insert_salted`.
+    /// If every salt tried is taken too, the value goes back to the leftover
+    /// of its asset and is returned, so that the caller doesn't count it as paid.
+    fn insert_leftover_share(hash: H256, utxo: TransactionOutput) -> Value {
+        let hash = if <UnspentOutputs<T>>::exists(hash) {
+            match Self::insert_salted(utxo.clone()) {
+                Ok(hash) => hash,
+                Err(_) => {
+                    runtime_io::print("leftover share kept for lack of a free salt");
+                    <LeftoverTotals<T>>::mutate(utxo.asset_id, |total| *total = total.saturating_add(utxo.value));
+                    return utxo.value;
+                }
+            }
+        } else {
+            Self::insert_utxo(hash, utxo);
+            hash
+        };
+        runtime_io::print("leftover share sent to");
+        runtime_io::print(hash.as_fixed_bytes() as &[u8]);
+        0
+    }
+
+    /// Create a plain native output out of thin air, returning its hash
+    fn create_output(value: Value, pubkey: H256) -> rstd::result::Result<H256, &'static str> {
+        Self::insert_salted(TransactionOutput {
+            value,
+            pubkey,
+            salt: 0,
+            condition: SpendCondition::Unconditional,
+            asset_id: NATIVE_ASSET,
+            expires_at: None,
+        })
+    }
+
+    /// Add `utxo` to the UTXO set under a salt of its own, returning its hash.
+    ///
+    /// The salt starts at the current block number and is incremented on
+    /// hash collision, up to `MINT_ATTEMPTS` times.
+    fn insert_salted(mut utxo: TransactionOutput) -> rstd::result::Result<H256, &'static str> {
+        let first_salt = Self::block_salt();
+
+        for attempt in 0..MINT_ATTEMPTS {
+            utxo.salt = first_salt.wrapping_add(attempt);
+            let hash = Self::output_hash(&utxo);
+
+            if !<UnspentOutputs<T>>::exists(hash) {
+                Self::insert_utxo(hash, utxo);
+                return Ok(hash);
+            }
+        }
+
+        Err("cannot mint due to hash collision")
+    }
+
+    /// Salt derived from the current block number.
+    ///
+    /// Block numbers wider than 64 bits are truncated to their low 64 bits,
+    /// so salts repeat every 2^64 blocks on such chains.
+    fn block_salt() -> u64 {
+        <system::Module<T>>::block_number().as_()
+    }
+
+    /// Salt of the leftover share of an asset paid to `authority` in the current block,
+    /// as given by `T::LeftoverSalt`
+    fn leftover_salt(authority: &H256, asset_id: AssetId, value: Value) -> u64 {
+        T::LeftoverSalt::next_salt(<system::Module<T>>::block_number(), authority, asset_id, value)
+    }
+
+    /// Update storage to reflect changes made by transaction.
+    ///
+    /// Deterministic: given the same state, block number and transaction,
+    /// it always writes the same storage, so re-executing a block after a
+    /// reorg yields the same UTXO hashes. The block number is the only input
+    /// besides state and transaction, through `CreatedAt` and `CreatedInBlock`.
+    /// Leftover shares and minted outputs are salted with it too.
+    fn update_storage(transaction: &Transaction, txid: H256, leftover: &BTreeMap<AssetId, Value>) -> Result {
+        // Calculate new leftover totals, after burning their part, before writing
+        // any of them so that an overflow of one asset leaves the others untouched
+        let burn_bps = Self::burn_fraction();
+        let mut new_totals = BTreeMap::new();
+        for (asset_id, value) in leftover.iter().filter(|(_, value)| **value != 0) {
+            let burnt = Self::bps_part(*value, burn_bps);
+            let new_total = Self::leftover_total(asset_id)
+                .checked_add(value - burnt)
+                .ok_or("Leftover overflow")?;
+            new_totals.insert(*asset_id, (new_total, burnt));
+        }
+
+        for (asset_id, (new_total, burnt)) in new_totals {
+            if burnt != 0 {
+                Self::deposit_event(Event::LeftoverBurnt(asset_id, burnt));
+            }
+            <LeftoverTotals<T>>::insert(asset_id, new_total);
+        }
+        if let Some(value) = leftover.get(&NATIVE_ASSET) {
+            <BlockLeftover<T>>::mutate(|total| *total = total.saturating_add(*value));
+        }
+
+        let track_spend_counts = Self::track_spend_counts();
+
+        // Storing updated leftover value
+        for input in &transaction.inputs {
+            if let Some(spent) = Self::remove_utxo(&input.parent_output) {
+                if track_spend_counts {
+                    <SpentCount<T>>::mutate(spent.pubkey, |count| *count += 1);
+                }
+            }
+        }
+
+        // Add new UTXO to be used by future transactions
+        for output in &transaction.outputs {
+            if track_spend_counts {
+                <ReceivedCount<T>>::mutate(output.pubkey, |count| *count += 1);
+            }
+
+            let hash = Self::output_hash(output);
+            Self::insert_utxo(hash, output.clone());
+            <OutputOrigin<T>>::insert(hash, txid);
+            Self::deposit_indexed_event(&[output.pubkey], Event::OutputCreated(output.pubkey, hash));
+        }
+
+        Ok(())
+    }
+
+    /// Remember a signature used by an executed transaction, forgetting
+    /// the oldest one if `MaxRecentSignatures` are remembered already
+    fn remember_signature(signature: Signature) {
+        let max = Self::max_recent_signatures();
+        if max == 0 {
+            return;
+        }
+
+        let mut start = <RecentSignaturesStart<T>>::get();
+        let end = <RecentSignaturesEnd<T>>::get();
+        if end.wrapping_sub(start) >= max {
+            if let Some(oldest) = <RecentSignatureQueue<T>>::take(start) {
+                <RecentSignatures<T>>::remove(oldest);
+            }
+            start = start.wrapping_add(1);
+            <RecentSignaturesStart<T>>::put(start);
+        }
+
+        <RecentSignatureQueue<T>>::insert(end, signature);
+        <RecentSignatures<T>>::insert(signature, <system::Module<T>>::block_number());
+        <RecentSignaturesEnd<T>>::put(end.wrapping_add(1));
+    }
+
+    /// Forget signatures used `SignatureWindow` or more blocks before `now`
+    fn expire_recent_signatures(now: T::BlockNumber) {
+        let window = Self::signature_window();
+        let mut start = <RecentSignaturesStart<T>>::get();
+        let end = <RecentSignaturesEnd<T>>::get();
+
+        while start != end {
+            if let Some(signature) = <RecentSignatureQueue<T>>::get(start) {
+                if let Some(used_at) = Self::recent_signature(signature) {
+                    if used_at.saturating_add(window) > now {
+                        break;
+                    }
+                }
+                <RecentSignatureQueue<T>>::remove(start);
+                <RecentSignatures<T>>::remove(signature);
+            }
+            start = start.wrapping_add(1);
+        }
+
+        <RecentSignaturesStart<T>>::put(start);
+    }
+
+    /// Deposit an event, counting it in `BlockEventCount`
+    fn deposit_event(event: Event) {
+        <BlockEventCount<T>>::mutate(|count| *count += 1);
+        <system::Module<T>>::deposit_event(<T as Trait>::Event::from(event).into());
+    }
+
+    /// Deposit an event and record it under each of the given topics in `EventTopics`
+    fn deposit_indexed_event(topics: &[H256], event: Event) {
+        let index = Self::block_event_count();
+        Self::deposit_event(event);
+
+        for topic in topics {
+            if !<EventTopics<T>>::exists(topic) {
+                <BlockTopics<T>>::mutate(|block_topics| block_topics.push(*topic));
+            }
+            <EventTopics<T>>::mutate(topic, |indices| indices.push(index));
+        }
+    }
+
+    /// Check that every configured genesis UTXO actually made it to storage.
+    ///
+    /// Distinct genesis outputs sharing a hash silently overwrite each other,
+    /// destroying value. Only meaningful before the first block is executed.
+    pub fn audit_genesis() -> Result {
+        ensure!(
+            Self::genesis_utxo_count() == Self::stored_utxo_count(),
+            "genesis utxo count mismatch"
+        );
+        Ok(())
+    }
+
+    /// Root of the trie formed by the `UnspentOutputs` entries that genesis
+    /// builds from `utxos`. Lets chain spec tooling check that a spec
+    /// reproduces the expected UTXO set.
+    #[cfg(feature = "std")]
+    pub fn expected_genesis_root(utxos: &[TransactionOutput]) -> H256 {
+        let storage = GenesisConfig::<T> {
+            initial_utxo: utxos.to_vec(),
+            ..Default::default()
+        }
+        .build_storage()
+        .expect("utxo genesis storage builds")
+        .0;
+
+        let entries: BTreeMap<_, _> = utxos
+            .iter()
+            .map(|utxo| <UnspentOutputs<T>>::key_for(Self::output_hash(utxo)))
+            .filter_map(|key| storage.get(&key).cloned().map(|value| (key, value)))
+            .collect();
+
+        BlakeTwo256::trie_root(entries)
+    }
+
+    /// UTXOs added and removed between two snapshots of the UTXO set, as
+    /// `(added, removed)` sorted by hash. Lets operators check what changed
+    /// across a runtime upgrade or over a period.
+    ///
+    /// Entries are told apart by hash alone, outputs stored under a hash
+    /// present in both snapshots are assumed to be the same.
+    #[cfg(feature = "std")]
+    pub fn diff_snapshots(
+        before: &[(H256, TransactionOutput)],
+        after: &[(H256, TransactionOutput)],
+    ) -> (Vec<H256>, Vec<H256>) {
+        use rstd::collections::btree_set::BTreeSet;
+
+        let before: BTreeSet<_> = before.iter().map(|(hash, _)| *hash).collect();
+        let after: BTreeSet<_> = after.iter().map(|(hash, _)| *hash).collect();
+
+        (
+            after.difference(&before).cloned().collect(),
+            before.difference(&after).cloned().collect(),
+        )
+    }
+
+    /// Human readable summary of the module storage, for node logs and support.
+    ///
+    /// Lists the pending leftover of every asset, the size of the UTXO set and
+    /// the first `DEBUG_DUMP_SAMPLE` UTXOs along with their locks, one per line.
+    #[cfg(feature = "std")]
+    pub fn debug_dump() -> String {
+        let mut dump = String::new();
+
+        for (asset_id, leftover) in <LeftoverTotals<T>>::enumerate() {
+            dump.push_str(&format!("leftover asset={} value={}\n", asset_id, leftover));
+        }
+        dump.push_str(&format!("utxo_count={}\n", Self::utxo_count()));
+
+        for (hash, output) in <UnspentOutputs<T>>::enumerate().take(DEBUG_DUMP_SAMPLE) {
+            dump.push_str(&format!(
+                "utxo {:?} value={} pubkey={:?} asset={} lock={:?}\n",
+                hash,
+                output.value,
+                output.pubkey,
+                output.asset_id,
+                <LockedOutputs<T>>::get(&hash),
+            ));
+        }
+
+        dump
+    }
+
+    /// Human readable address of `pubkey`, for display in wallets.
+    ///
+    /// Encodes the key as bech32 under the `ADDRESS_HRP` prefix, so the
+    /// address carries a checksum catching typos. See `address_to_pubkey`.
+    #[cfg(feature = "std")]
+    pub fn pubkey_to_address(pubkey: &H256) -> String {
+        let mut data = Self::regroup_bits(pubkey.as_bytes(), 8, 5, true).expect("padding always succeeds; qed");
+        let checksum = Self::address_polymod(ADDRESS_HRP, &data, &[0; 6]) ^ 1;
+        data.extend((0..6).map(|i| ((checksum >> (5 * (5 - i))) & 31) as u8));
+
+        let mut address = String::from(ADDRESS_HRP);
+        address.push('1');
+        address.extend(data.iter().map(|group| ADDRESS_CHARSET[*group as usize] as char));
+        address
+    }
+
+    /// Public key encoded by an address built by `pubkey_to_address`.
+    ///
+    /// Addresses may be written in upper case, but not in mixed case.
+    #[cfg(feature = "std")]
+    pub fn address_to_pubkey(address: &str) -> rstd::result::Result<H256, &'static str> {
+        let lower = address.to_lowercase();
+        ensure!(lower == address || address.to_uppercase() == address, "invalid address");
+
+        let separator = lower.rfind('1').ok_or("invalid address")?;
+        ensure!(&lower[..separator] == ADDRESS_HRP, "invalid address");
+        let groups = lower[separator + 1..]
+            .bytes()
+            .map(|c| ADDRESS_CHARSET.iter().position(|&d| d == c).map(|group| group as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or("invalid address")?;
+        ensure!(groups.len() > 6, "invalid address");
+
+        let (data, checksum) = groups.split_at(groups.len() - 6);
+        ensure!(Self::address_polymod(ADDRESS_HRP, data, checksum) == 1, "address checksum mismatch");
+
+        match Self::regroup_bits(data, 5, 8, false) {
+            Some(ref bytes) if bytes.len() == 32 => Ok(H256::from_slice(bytes)),
+            _ => Err("invalid address"),
+        }
+    }
+
+    /// Bech32 checksum polynomial over the prefix `hrp` followed by `data` and `checksum`
+    #[cfg(feature = "std")]
+    fn address_polymod(hrp: &str, data: &[u8], checksum: &[u8]) -> u32 {
+        const GENERATORS: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+
+        let values = hrp
+            .bytes()
+            .map(|c| c >> 5)
+            .chain(Some(0))
+            .chain(hrp.bytes().map(|c| c & 31))
+            .chain(data.iter().cloned())
+            .chain(checksum.iter().cloned());
+
+        values.fold(1, |chk, value| {
+            let top = chk >> 25;
+            let chk = ((chk & 0x1ff_ffff) << 5) ^ u32::from(value);
+            GENERATORS
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| (top >> i) & 1 == 1)
+                .fold(chk, |chk, (_, generator)| chk ^ generator)
+        })
+    }
+
+    /// Regroup `data`, made of `from`-bit groups, into `to`-bit groups.
+    /// Without `pad`, fails if bits are left over or incomplete groups are nonzero.
+    #[cfg(feature = "std")]
+    fn regroup_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+        let max = (1u32 << to) - 1;
+        let (mut acc, mut bits) = (0u32, 0u32);
+        let mut groups = Vec::new();
+
+        for value in data {
+            acc = (acc << from) | u32::from(*value);
+            bits += from;
+            while bits >= to {
+                bits -= to;
+                groups.push(((acc >> bits) & max) as u8);
+            }
+            acc &= (1 << bits) - 1;
+        }
+
+        if pad {
+            if bits > 0 {
+                groups.push(((acc << (to - bits)) & max) as u8);
+            }
+        } else if bits >= from || acc != 0 {
+            return None;
+        }
+
+        Some(groups)
+    }
+
+    /// Number of outputs in the UTXO set. Iterates the whole set.
+    fn stored_utxo_count() -> u64 {
+        <UnspentOutputs<T>>::enumerate().count() as u64
+    }
+
+    /// Add an output to the UTXO set, keeping secondary indexes in sync.
+    ///
+    /// A lock only ever applies to the output it was placed on. A lock record
+    /// found at the hash of a new output is stale and gets cleared, so that
+    /// outputs created on their own, like leftover shares, are never born locked.
+    ///
+    /// The hash commits to the whole output, so an output already stored under
+    /// `hash` is left alone, along with its index entries and lock.
+    fn insert_utxo(hash: H256, output: TransactionOutput) {
+        if <UnspentOutputs<T>>::exists(hash) {
+            runtime_io::print("utxo already exists");
+            return;
+        }
+        if <LockedOutputs<T>>::take(&hash).is_some() {
+            runtime_io::print("stale lock cleared for new utxo");
+        }
+        <OwnedOutputs<T>>::mutate(output.pubkey, |owned| owned.push(hash));
+        let now = <system::Module<T>>::block_number();
+        <CreatedAt<T>>::insert(hash, now);
+        <CreatedInBlock<T>>::mutate(now, |created| created.push(hash));
+        if let Some(at) = output.expires_at {
+            <ExpiringOutputs<T>>::mutate(<T::BlockNumber as As<u64>>::sa(at), |expiring| expiring.push(hash));
+        }
+        <UtxoCount<T>>::mutate(|count| *count += 1);
+        <UnspentOutputs<T>>::insert(hash, output);
+    }
+
+    /// Remove an output from the UTXO set, keeping secondary indexes in sync
+    fn remove_utxo(hash: &H256) -> Option<TransactionOutput> {
+        let output = <UnspentOutputs<T>>::take(hash)?;
+        <UtxoCount<T>>::mutate(|count| *count = count.saturating_sub(1));
+        <CreatedAt<T>>::remove(hash);
+        <ReservedOutputs<T>>::remove(hash);
+        <OwnerNonce<T>>::remove(hash);
+        // Spendable outputs may still carry a lock whose deadline passed
+        <LockedOutputs<T>>::remove(hash);
+
+        let mut owned = <OwnedOutputs<T>>::get(&output.pubkey);
+        owned.retain(|h| h != hash);
+        if owned.is_empty() {
+            <OwnedOutputs<T>>::remove(&output.pubkey);
+        } else {
+            <OwnedOutputs<T>>::insert(&output.pubkey, owned);
+        }
+
+        Some(output)
+    }
+
+    /// Bring storage written by an older runtime up to `CURRENT_STORAGE_VERSION`.
+    ///
+    /// Migrations are applied one version at a time, so a chain that skipped
+    /// several upgrades is walked through every intermediate step.
+    pub fn on_runtime_upgrade() {
+        let mut version = Self::storage_version();
+        while version < CURRENT_STORAGE_VERSION {
+            match version {
+                // Waits for `migrate_outputs_v0`
+                0 => return,
+                1 => Self::migrate_to_v2(),
+                2 => Self::migrate_to_v3(),
+                _ => {}
+            }
+            version += 1;
+            <StorageVersion<T>>::put(version);
+        }
+    }
+
+    /// Raw storage key of the `UnspentOutputs` entry of `hash`, which is
+    /// the same for the version 0 plain map and the current linked map
+    fn unspent_output_key(hash: &H256) -> Vec<u8> {
+        let mut key = UNSPENT_OUTPUTS_PREFIX.to_vec();
+        hash.encode_to(&mut key);
+        runtime_io::blake2_256(&key).to_vec()
+    }
+
+    /// Version 1 turns `UnspentOutputs` into a linked map and introduces the
+    /// owner index. Moves the version 0 entry of `hash`, if any, into both.
+    fn migrate_output_v0(hash: H256) -> Result {
+        let key = Self::unspent_output_key(&hash);
+        let output: TransactionOutputV0 = match unhashed::get_raw(&key) {
+            Some(raw) => Decode::decode(&mut &raw[..]).ok_or("invalid version 0 output")?,
+            None => return Ok(()),
+        };
+
+        // The linked map would read the old value as its own entry
+        unhashed::kill(&key);
+        Self::insert_utxo(hash, output.into());
+        Ok(())
+    }
+
+    /// Version 2 keeps leftover per asset; what was pending belongs to the native one
+    fn migrate_to_v2() {
+        let leftover = <LeftoverTotal<T>>::take();
+        if leftover != 0 {
+            <LeftoverTotals<T>>::insert(NATIVE_ASSET, leftover);
+        }
+    }
+
+    /// Remove the outputs expiring at block `now` which are still unspent,
+    /// adding their value to the leftover of their asset
+    fn expire_outputs(now: T::BlockNumber) {
+        for hash in <ExpiringOutputs<T>>::take(now) {
+            // Spent in the meantime
+            let (asset_id, value) = match <UnspentOutputs<T>>::get(&hash) {
+                Some(output) => (output.asset_id, output.value),
+                None => continue,
+            };
+            match Self::leftover_total(asset_id).checked_add(value) {
+                Some(total) => <LeftoverTotals<T>>::insert(asset_id, total),
+                None => {
+                    runtime_io::print("Leftover overflow");
+                    continue;
+                }
+            }
+
+            Self::remove_utxo(&hash);
+            Self::deposit_event(Event::OutputExpired(hash, value));
+        }
+    }
+
+    /// Version 3 counts the UTXO set instead of enumerating it
+    fn migrate_to_v3() {
+        <UtxoCount<T>>::put(Self::stored_utxo_count());
+    }
+
+    /// Deposit `UtxoSetLarge` if the UTXO set just grew above `UtxoSetWarnThreshold`
+    fn warn_if_utxo_set_large() {
+        let count = Self::utxo_count();
+        let large = count > Self::utxo_set_warn_threshold();
+        if large != <UtxoSetWarned<T>>::get() {
+            if large {
+                Self::deposit_event(Event::UtxoSetLarge(count));
+            }
+            <UtxoSetWarned<T>>::put(large);
+        }
+    }
+
+    pub fn lock_utxo(hash: &H256, until: Option<T::BlockNumber>) -> Result {
+        let now = <system::Module<T>>::block_number();
+        ensure!(!Self::is_locked_at(hash, now), "utxo is already locked");
+        ensure!(<UnspentOutputs<T>>::exists(hash), "utxo does not exist");
+
+        // Genesis outputs count as created at block 0
+        let age = now.checked_sub(&Self::created_at(hash)).unwrap_or_else(Zero::zero);
+        ensure!(age >= Self::lock_grace_period(), "utxo too new to lock");
+
+        if let Some(until) = until {
+            ensure!(until > now, "block number is in the past");
+            ensure!(until - now <= Self::max_lock_duration(), "lock duration too long");
+            Self::lock_until(*hash, until);
+        } else {
+            <LockedOutputs<T>>::insert(hash, LockStatus::Locked);
+        }
+
+        Ok(())
+    }
+
+    /// Time-lock the UTXO stored under `hash` until `until`, and schedule the
+    /// release of the lock at that block
+    fn lock_until(hash: H256, until: T::BlockNumber) {
+        <LockedOutputs<T>>::insert(hash, LockStatus::LockedUntil(until));
+        <LockExpiry<T>>::mutate(until, |expiring| expiring.push(hash));
+    }
+
+    /// Release the time-locks ending at block `now`.
+    ///
+    /// Locks cancelled, replaced or whose output was spent in the meantime
+    /// are left alone. Locks placed before the expiry index existed are not
+    /// scheduled and stay in storage until their output is spent or unlocked.
+    fn purge_expired_locks(now: T::BlockNumber) {
+        for hash in <LockExpiry<T>>::take(now) {
+            match <LockedOutputs<T>>::get(&hash) {
+                Some(LockStatus::LockedUntil(until)) if until <= now => {
+                    <LockedOutputs<T>>::remove(&hash);
+                    Self::deposit_event(Event::OutputUnlocked(hash));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Outputs of `pubkey` which it will be able to spend at the given future `block`.
+    ///
+    /// Includes outputs which are not locked at all, as well as those whose
+    /// time-lock expires no later than `block`.
+    pub fn spendable_at(pubkey: &H256, block: T::BlockNumber) -> Vec<(H256, TransactionOutput)> {
+        Self::owned_outputs(pubkey)
+            .into_iter()
+            .filter(|hash| !Self::is_locked_at(hash, block))
+            .filter_map(|hash| <UnspentOutputs<T>>::get(&hash).map(|output| (hash, output)))
+            .filter(|(hash, output)| Self::spending_keys(hash, output, block.as_()).contains(&pubkey))
+            .collect()
+    }
+
+    /// Value of a UTXO which can actually be spent at block `now`: nothing while
+    /// it is locked, what demurrage left of it otherwise. `None` if the UTXO
+    /// is not in the set.
+    pub fn effective_value(hash: &H256, now: T::BlockNumber) -> Option<Value> {
+        let output = <UnspentOutputs<T>>::get(hash)?;
+        if Self::is_locked_at(hash, now) {
+            return Some(0);
+        }
+        Some(output.value - Self::decay(hash, &output, now.as_()))
+    }
+
+    /// Smallest and largest value among the native outputs `pubkey` can spend now,
+    /// or `None` if it can't spend any
+    pub fn value_extremes(pubkey: &H256) -> Option<(Value, Value)> {
+        Self::spendable_at(pubkey, <system::Module<T>>::block_number())
+            .into_iter()
+            .filter(|(_, output)| output.asset_id == NATIVE_ASSET)
+            .fold(None, |extremes, (_, output)| match extremes {
+                Some((min, max)) => Some((output.value.min(min), output.value.max(max))),
+                None => Some((output.value, output.value)),
+            })
+    }
+
+    /// Fewest native outputs `pubkey` can spend now whose values add up to
+    /// exactly `target`, so that paying it needs no change output.
+    ///
+    /// Tries combinations of up to `MAX_EXACT_INPUTS` outputs, smallest first,
+    /// and returns `None` if none of them adds up. Still grows quickly with
+    /// the number of outputs, so it is meant for RPC and off-chain use only.
+    pub fn select_exact(pubkey: &H256, target: Value) -> Option<Vec<(H256, TransactionOutput)>> {
+        if target == 0 {
+            return None;
+        }
+
+        let mut candidates: Vec<_> = Self::spendable_at(pubkey, <system::Module<T>>::block_number())
+            .into_iter()
+            .filter(|(_, output)| output.asset_id == NATIVE_ASSET && output.value <= target)
+            .collect();
+        candidates.sort_by_key(|(_, output)| output.value);
+
+        let mut picked = Vec::new();
+        for size in 1..=MAX_EXACT_INPUTS.min(candidates.len()) {
+            if Self::pick_exact(&candidates, 0, size, target, &mut picked) {
+                return Some(picked.into_iter().map(|index| candidates[index].clone()).collect());
+            }
+        }
+        None
+    }
+
+    /// Pick `size` more of the `candidates` sorted by value, starting at `from`,
+    /// adding up to `remaining`. Indices of the picked ones are pushed to `picked`.
+    fn pick_exact(
+        candidates: &[(H256, TransactionOutput)],
+        from: usize,
+        size: usize,
+        remaining: Value,
+        picked: &mut Vec<usize>,
+    ) -> bool {
+        if size == 0 {
+            return remaining == 0;
+        }
+        for index in from..candidates.len() {
+            let value = candidates[index].1.value;
+            // Candidates are sorted, so all the next ones are too large as well
+            if value > remaining {
+                break;
+            }
+            picked.push(index);
+            if Self::pick_exact(candidates, index + 1, size - 1, remaining - value, picked) {
+                return true;
+            }
+            picked.pop();
+        }
+        false
+    }
+
+    /// Number of blocks elapsed between the creation of an output and `now`.
+    ///
+    /// Returns `None` if the output is not in the UTXO set, if its creation
+    /// block is not known (as for genesis outputs) or if `now` precedes it.
+    pub fn confirmations(hash: &H256, now: T::BlockNumber) -> Option<T::BlockNumber> {
+        if !<UnspentOutputs<T>>::exists(hash) || !<CreatedAt<T>>::exists(hash) {
+            return None;
+        }
+        now.checked_sub(&Self::created_at(hash))
+    }
+
+    /// Number of unspent outputs per age at block `now`.
+    ///
+    /// `buckets` are ascending upper bounds: entry `i` of the result counts
+    /// outputs younger than `buckets[i]` but not younger than `buckets[i - 1]`,
+    /// and one extra last entry counts all the older ones. Outputs without
+    /// a known creation block, such as genesis ones, count as created at block 0.
+    ///
+    /// Iterates the whole UTXO set, so it is meant for RPC and off-chain use only,
+    /// never from within a block.
+    pub fn utxo_age_histogram(now: T::BlockNumber, buckets: &[T::BlockNumber]) -> Vec<u64> {
+        let mut histogram = vec![0u64; buckets.len() + 1];
+
+        for (hash, _) in <UnspentOutputs<T>>::enumerate() {
+            let age = now.checked_sub(&Self::created_at(hash)).unwrap_or_else(Zero::zero);
+            let bucket = buckets.iter().position(|bound| age < *bound).unwrap_or(buckets.len());
+            histogram[bucket] += 1;
+        }
+
+        histogram
+    }
+
+    /// Number and total value of the outputs a `sweep` of `pubkey` would merge.
+    ///
+    /// Advisory only: reads the owner index without checking any signature.
+    pub fn consolidation_savings(pubkey: &H256) -> (u64, Value) {
+        Self::sweepable_outputs(pubkey)
+            .iter()
+            .fold((0, 0), |(count, total), (_, output)| (count + 1, total.saturating_add(output.value)))
+    }
+
+    /// Hashes of the outputs created in `block`, including since spent ones.
+    /// Empty for blocks older than `CreationHistory`.
+    pub fn outputs_created_in(block: T::BlockNumber) -> Vec<H256> {
+        <CreatedInBlock<T>>::get(block)
+    }
+
+    /// Whether each of the outputs is in the UTXO set, in the order of `hashes`
+    pub fn exist_many(hashes: &[H256]) -> Vec<bool> {
+        hashes.iter().map(|hash| <UnspentOutputs<T>>::exists(hash)).collect()
+    }
+
+    /// Whether outputs owned by `pubkey` are currently barred from being spent
+    pub fn is_account_frozen(pubkey: &H256) -> bool {
+        Self::frozen_accounts(pubkey)
+    }
+
+    /// Whether a lock on the UTXO is in force at the given block
+    fn is_locked_at(hash: &H256, block: T::BlockNumber) -> bool {
+        match <LockedOutputs<T>>::get(hash) {
+            Some(LockStatus::Locked) => true,
+            Some(LockStatus::LockedUntil(until)) => block < until,
+            None => false,
+        }
+    }
+
+    /// Message the owner of a UTXO signs to cancel its time-lock early, as `owner_payload`.
+    /// Domain separated, so that it can't be mistaken for a spend authorization.
+    pub fn cancel_lock_payload(hash: &H256) -> Vec<u8> {
+        Self::owner_payload(b"cancel_lock:", hash)
+    }
+
+    /// Message signed to donate a UTXO: domain separated and bound to the
+    /// launch of the chain, so that neither spend signatures nor donations
+    /// signed for another launch can be used
+    pub fn donate_payload(hash: &H256) -> Vec<u8> {
+        let mut payload = b"donate:".to_vec();
+        payload.extend_from_slice(Self::launch_hash().as_bytes());
+        payload.extend_from_slice(hash.as_bytes());
+        payload
+    }
+
+    /// Message signed to relock a UTXO until `new_until`: domain separated and
+    /// bound to the launch of the chain and to the new deadline
+    pub fn relock_payload(hash: &H256, new_until: T::BlockNumber) -> Vec<u8> {
+        let mut payload = b"relock:".to_vec();
+        payload.extend_from_slice(Self::launch_hash().as_bytes());
+        payload.extend_from_slice(hash.as_bytes());
+        new_until.encode_to(&mut payload);
+        payload
+    }
+
+    /// Message the owner of a UTXO signs to authorize `action` on it: domain
+    /// separated, and bound to the launch of the chain and to the current
+    /// `OwnerNonce` of the UTXO, so that it can't be replayed once used
+    fn owner_payload(action: &[u8], hash: &H256) -> Vec<u8> {
+        let mut payload = action.to_vec();
+        payload.extend_from_slice(Self::launch_hash().as_bytes());
+        payload.extend_from_slice(hash.as_bytes());
+        Self::owner_nonce(hash).encode_to(&mut payload);
+        payload
+    }
+
+    /// Message the owner of a UTXO signs to reserve it, as `owner_payload`
+    pub fn reserve_payload(hash: &H256) -> Vec<u8> {
+        Self::owner_payload(b"reserve:", hash)
+    }
+
+    /// Message an authority signs to claim its kept leftover shares
+    pub fn claim_payload(pubkey: &H256) -> Vec<u8> {
+        let mut payload = b"claim_rewards:".to_vec();
+        payload.extend_from_slice(pubkey.as_bytes());
+        payload
+    }
+
+    /// Message the owner of a UTXO signs to release its reservation, as `owner_payload`
+    pub fn release_payload(hash: &H256) -> Vec<u8> {
+        Self::owner_payload(b"release:", hash)
+    }
+
+    /// Ensure `owner_sig` is a signature of `payload` by the owner of the UTXO,
+    /// which wasn't used recently
+    fn check_owner_signature(hash: &H256, payload: &[u8], owner_sig: &Signature) -> Result {
+        let output = <UnspentOutputs<T>>::get(hash).ok_or("utxo does not exist")?;
+        ensure!(
+            !<RecentSignatures<T>>::exists(owner_sig),
+            InputFailure::ReplayedSignature.message()
+        );
+        ensure!(
+            sr25519_verify(owner_sig.as_fixed_bytes(), payload, &output.pubkey),
+            "signature must be valid"
+        );
+        Ok(())
+    }
+
+    /// Record an owner signature as used, once the action it authorized is done.
+    /// Moves the UTXO on to its next `OwnerNonce`, so that the signature is no
+    /// longer valid even after being forgotten by `RecentSignatures`.
+    fn use_owner_signature(hash: &H256, owner_sig: Signature) {
+        <OwnerNonce<T>>::mutate(hash, |nonce| *nonce = nonce.wrapping_add(1));
+        Self::remember_signature(owner_sig);
+    }
+
+    /// Whether `ReceivedCount` and `SpentCount` are maintained.
+    ///
+    /// Only if the runtime is built with the `spend-counts` feature: the counters
+    /// cost extra storage writes per transaction, and being decided at compile
+    /// time, they cost nothing at all to chains built without them.
+    pub fn track_spend_counts() -> bool {
+        cfg!(any(test, feature = "spend-counts"))
+    }
+
+    /// INSECURE: whether `check_transaction` accepts inputs without a valid signature,
+    /// letting anyone spend any output. Meant for quick testing on development chains.
+    ///
+    /// Set through `AllowUnsignedSpend` in the genesis configuration, but always
+    /// false unless the runtime is built with the `insecure-unsigned-spend` feature,
+    /// so that production builds can't be configured into accepting such spends.
+    pub fn allow_unsigned_spend() -> bool {
+        cfg!(any(test, feature = "insecure-unsigned-spend")) && <AllowUnsignedSpend<T>>::get()
+    }
+
+    pub fn unlock_utxo(hash: &H256) -> Result {
+        ensure!(<LockedOutputs<T>>::exists(hash), "utxo is not locked");
+        <LockedOutputs<T>>::remove(hash);
+        Ok(())
+    }
+}
+
+/// Tests for this module
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use primitives::{ed25519, sr25519, Blake2Hasher, Pair, H256};
+    use runtime_io::with_externalities;
+    use std::cell::RefCell;
+    use runtime_primitives::{
+        testing::{Digest, DigestItem, Header},
+        traits::{BlakeTwo256, IdentityLookup, OnFinalize, OnInitialize},
+        BuildStorage,
+    };
+    use support::{assert_err, assert_ok, impl_outer_event, impl_outer_origin};
+
+    impl_outer_origin! {
+        pub enum Origin for Test {}
+    }
+
+    mod utxo {
+        pub use super::super::Event;
+    }
+
+    impl_outer_event! {
+        pub enum TestEvent for Test {
+            utxo,
+        }
+    }
+
+    #[derive(Clone, Eq, PartialEq)]
+    pub struct Test;
+    impl system::Trait for Test {
+        type Origin = Origin;
+        type Index = u64;
+        type BlockNumber = u64;
+        type Hash = H256;
+        type Hashing = BlakeTwo256;
+        type Digest = Digest;
+        type AccountId = u64;
+        type Lookup = IdentityLookup<Self::AccountId>;
+        type Header = Header;
+        type Event = TestEvent;
+        type Log = DigestItem;
+    }
+    impl Trait for Test {
+        type Event = TestEvent;
+        type LeftoverSalt = TestLeftoverSalt;
+        type AuthorityToPubkey = HashedAuthorityKey;
+    }
+
+    // Pays authorities to the hash of their key, as chains with authority keys
+    // of another kind than the UTXO keys would need to
+    pub struct HashedAuthorityKey;
+    impl Convert<AuthorityId, H256> for HashedAuthorityKey {
+        fn convert(authority: AuthorityId) -> H256 {
+            BlakeTwo256::hash(&authority.0)
+        }
+    }
+
+    thread_local! {
+        // Next salt handed out by `TestLeftoverSalt`, unless the default ones are used
+        static NEXT_LEFTOVER_SALT: RefCell<Option<u64>> = RefCell::new(None);
+    }
+
+    // Hands out consecutive salts once `NEXT_LEFTOVER_SALT` is set, default ones otherwise
+    pub struct TestLeftoverSalt;
+    impl LeftoverSaltProvider<u64> for TestLeftoverSalt {
+        fn next_salt(block: u64, authority: &H256, asset_id: AssetId, value: Value) -> u64 {
+            NEXT_LEFTOVER_SALT.with(|next| match next.borrow_mut().as_mut() {
+                Some(salt) => {
+                    *salt += 1;
+                    *salt - 1
+                }
+                None => <() as LeftoverSaltProvider<u64>>::next_salt(block, authority, asset_id, value),
+            })
+        }
+    }
+
+    type Utxo = Module<Test>;
+
+    // Events deposited by this module so far
+    fn utxo_events() -> Vec<Event> {
+        <system::Module<Test>>::events()
+            .into_iter()
+            .filter_map(|record| match record.event {
+                TestEvent::utxo(event) => Some(event),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Test set up
+    // Alice's Public Key: Pair::from_seed(*b"12345678901234567890123456789012");
+    const ALICE_KEY: [u8; 32] = [68, 169, 150, 190, 177, 238, 247, 189, 202, 185, 118, 171, 109, 44, 162, 97, 4, 131, 65, 100, 236, 242, 143, 179, 117, 96, 5, 118, 252, 198, 235, 15];
+
+    // Alice's signature of the transaction spending alice_utxo() to a 100 value UTXO of hers with salt 2
+    const ALICE_SIG: [u8; 64] = [234, 241, 22, 84, 124, 33, 249, 140, 15, 16, 109, 115, 218, 49, 233, 117, 41, 66, 150, 252, 216, 95, 4, 109, 48, 126, 228, 47, 254, 191, 158, 59, 36, 154, 227, 65, 67, 145, 12, 200, 97, 141, 156, 90, 187, 228, 21, 175, 177, 221, 31, 70, 124, 85, 164, 69, 114, 5, 62, 183, 11, 117, 63, 8];
+
+    // Alice's signature of the transaction spending alice_utxo_100() to a 100 value UTXO of hers with salt 2
+    const ALICE_SIG100: [u8; 64] = [64, 50, 128, 49, 163, 91, 68, 119, 16, 49, 175, 185, 44, 176, 199, 213, 143, 193, 175, 144, 70, 187, 224, 161, 181, 247, 53, 83, 34, 139, 176, 61, 51, 130, 36, 213, 84, 201, 113, 80, 254, 155, 102, 234, 61, 39, 21, 88, 25, 80, 8, 80, 1, 192, 200, 78, 106, 248, 128, 150, 186, 129, 86, 4];
+
+    // Alice's Secret Seed, from which ALICE_KEY is derived
+    const ALICE_SEED: [u8; 32] = [157, 97, 177, 157, 239, 253, 90, 96, 186, 132, 74, 244, 146, 236, 44, 196, 68, 73, 197, 105, 123, 50, 105, 25, 112, 59, 172, 3, 28, 174, 127, 96];
+
+    // Alice's key pair, for signing transactions built within a test
+    fn alice_pair() -> sr25519::Pair {
+        sr25519::Pair::from_seed(ALICE_SEED)
+    }
+
+    // Bob's key pair, used whenever a second party is needed
+    fn bob_pair() -> sr25519::Pair {
+        sr25519::Pair::from_seed(*b"bob's secret seed, 32 bytes long")
+    }
+
+    // Signs every input of the transaction with the given key pair
+    fn sign_inputs(transaction: &mut Transaction, pair: &sr25519::Pair) {
+        let signature = Signature::from_slice(&pair.sign(&Utxo::signing_payload(transaction)).0);
+        for input in transaction.inputs.iter_mut() {
+            input.signature = signature;
+        }
+    }
+
+    // The transaction with every input signed by Alice
+    fn signed(mut transaction: Transaction) -> Transaction {
+        sign_inputs(&mut transaction, &alice_pair());
+        transaction
+    }
+
+    // Creates a max value UTXO for Alice
+    fn alice_utxo() -> (H256, TransactionOutput) {
+        let transaction = TransactionOutput {
+            value: Value::max_value(),
+            pubkey: H256::from_slice(&ALICE_KEY),
+            salt: 0,
+            ..Default::default()
+        };
+
+        (BlakeTwo256::hash_of(&transaction), transaction)
+    }
+
+    // Creates a 100 value UTXO for Alice
+    fn alice_utxo_100() -> (H256, TransactionOutput) {
+        let transaction = TransactionOutput {
+            value: 100,
+            pubkey: H256::from_slice(&ALICE_KEY),
+            salt: 0,
+            ..Default::default()
+        };
+
+        (BlakeTwo256::hash_of(&transaction), transaction)
+    }
+
+    // This function basically just builds a genesis storage key/value store according to
+    // our desired mockup.
+    fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+        new_test_ext_with(vec![alice_utxo().1, alice_utxo_100().1])
+    }
+
+    // Same as `new_test_ext`, but with a custom set of genesis UTXOs
+    fn new_test_ext_with(initial_utxo: Vec<TransactionOutput>) -> runtime_io::TestExternalities<Blake2Hasher> {
+        let mut t = system::GenesisConfig::<Test>::default()
+            .build_storage()
+            .unwrap()
+            .0;
+        t.extend(
+            GenesisConfig::<Test> {
+                initial_utxo,
+                ..Default::default()
+            }
+            .build_storage()
+            .unwrap()
+            .0,
+        );
+        t.into()
+    }
+
+    // Advances the chain block by block up to `n`, initializing each block,
+    // so that everything depending on the block number sees it move
+    fn run_to_block(n: u64) {
+        while <system::Module<Test>>::block_number() < n {
+            let next = <system::Module<Test>>::block_number() + 1;
+            <system::Module<Test>>::set_block_number(next);
+            Utxo::on_initialize(next);
+        }
+    }
+
+    // Panics unless the secondary indexes agree with the UTXO set. Covers:
+    // - `OwnedOutputs`: every UTXO is listed exactly once under its owner, and
+    //   owners of some UTXO list nothing else. Owners of no UTXO at all can't
+    //   be enumerated and so are not covered.
+    // - `LeftoverTotals`: assets with no leftover have no entry.
+    // Other indexes are plain maps which can't be enumerated.
+    fn assert_indexes_consistent() {
+        let mut owned = BTreeMap::<H256, Vec<H256>>::new();
+        for (hash, output) in <UnspentOutputs<Test>>::enumerate() {
+            owned.entry(output.pubkey).or_insert_with(Vec::new).push(hash);
+        }
+        for (owner, mut hashes) in owned {
+            let mut indexed = Utxo::owned_outputs(owner);
+            hashes.sort();
+            indexed.sort();
+            assert_eq!(indexed, hashes, "owner index diverged for {:?}", owner);
+        }
+
+        for (asset_id, total) in <LeftoverTotals<Test>>::enumerate() {
+            assert!(total != 0, "zero leftover stored for asset {}", asset_id);
+        }
+    }
+
+    // Exercise 1: Fortify transactions against attacks
+    // ================================================
+    //
+    // The following tests simulate malicious UTXO transactions
+    // Implement the check_transaction() function to thwart such attacks
+    //
+    // Hint: Examine types CheckResult, CheckInfo for the expected behaviors of this function
+    // Hint: Make this function public, as it will be later used outside of this module
+
+    #[test]
+    fn attack_with_empty_transactions() {
+        with_externalities(&mut new_test_ext(), || {
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, Transaction::default()), // an empty trx
+                "no inputs"
+            );
+
+            assert_err!(
+                Utxo::execute(
+                    Origin::INHERENT,
+                    Transaction {
+                        inputs: vec![TransactionInput::default()], // an empty trx
+                        outputs: vec![],
+                    }
+                ),
+                "no outputs"
+            );
+        });
+    }
+
+    #[test]
+    fn attack_by_double_counting_input() {
+        with_externalities(&mut new_test_ext(), || {
+            let (parent_hash, _) = alice_utxo();
+
+            println!("PARENT HASH: {:x?}: ", parent_hash);
+            let transaction = Transaction {
+                inputs: vec![
+                    TransactionInput {
+                        parent_output: parent_hash,
+                        signature: Signature::from_slice(&ALICE_SIG),
+                    },
+                    TransactionInput {
+                        parent_output: parent_hash, // Double spending input!
+                        signature: Signature::from_slice(&ALICE_SIG),
+                    },
+                ],
+                outputs: vec![TransactionOutput {
+                    value: 100,
+                    pubkey: H256::from_slice(&ALICE_KEY),
+                    salt: 0,
+                    ..Default::default()
+                }],
+            };
+
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, transaction),
+                "each input must only be used once"
+            );
+        });
+    }
+
+    #[test]
+    fn attack_by_double_counting_input_with_other_signature() {
+        with_externalities(&mut new_test_ext(), || {
+            let (parent_hash, _) = alice_utxo();
+
+            let transaction = Transaction {
+                inputs: vec![
+                    TransactionInput {
+                        parent_output: parent_hash,
+                        signature: Signature::from_slice(&ALICE_SIG),
+                    },
+                    TransactionInput {
+                        parent_output: parent_hash, // Same input, different signature
+                        signature: H512::random(),
+                    },
+                ],
+                outputs: vec![TransactionOutput {
+                    value: 100,
+                    pubkey: H256::from_slice(&ALICE_KEY),
+                    salt: 2,
+                    ..Default::default()
+                }],
+            };
+
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, transaction),
+                "each input must only be used once"
+            );
+        });
+    }
+
+    #[test]
+    fn attack_by_double_generating_output() {
+        with_externalities(&mut new_test_ext(), || {
+            let (parent_hash, _) = alice_utxo();
+
+            let transaction = Transaction {
+                inputs: vec![TransactionInput {
+                    parent_output: parent_hash,
+                    signature: Signature::from_slice(&ALICE_SIG),
+                }],
+                outputs: vec![
+                    TransactionOutput {
+                        value: 100,
+                        pubkey: H256::from_slice(&ALICE_KEY),
+                        salt: 0,
+                        ..Default::default()
+                    },
+                    TransactionOutput {
+                        // Same output defined here!
+                        value: 100,
+                        pubkey: H256::from_slice(&ALICE_KEY),
+                        salt: 0,
+                        ..Default::default()
+                    },
+                ],
+            };
+
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, transaction),
+                "each output must be defined only once"
+            );
+        });
+    }
+
+    #[test]
+    fn attack_with_invalid_signature() {
+        with_externalities(&mut new_test_ext(), || {
+            let (parent_hash, _) = alice_utxo();
+
+            let transaction = Transaction {
+                inputs: vec![TransactionInput {
+                    parent_output: parent_hash,
+                    signature: H512::random(), // Just a random signature!
+                }],
+                outputs: vec![TransactionOutput {
+                    value: 100,
+                    pubkey: H256::from_slice(&ALICE_KEY),
+                    salt: 0,
+                    ..Default::default()
+                }],
+            };
+
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, transaction),
+                "signature must be valid"
+            );
+        });
+    }
+
+    #[test]
+    fn attack_by_permanently_sinking_outputs() {
+        with_externalities(&mut new_test_ext(), || {
+            let (parent_hash, _) = alice_utxo();
+
+            let transaction = signed(Transaction {
+                inputs: vec![TransactionInput {
+                    parent_output: parent_hash,
+                    signature: Signature::default(),
+                }],
+                outputs: vec![TransactionOutput {
+                    value: 0, // A 0 value output burns this output forever!
+                    pubkey: H256::from_slice(&ALICE_KEY),
+                    salt: 0,
+                    ..Default::default()
+                }],
+            });
+
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, transaction),
+                "output value must be nonzero"
+            );
+        });
+    }
+
+    #[test]
+    fn attack_by_overflowing() {
+        with_externalities(&mut new_test_ext(), || {
+            let (parent_hash, _) = alice_utxo();
+
+            let transaction = signed(Transaction {
+                inputs: vec![TransactionInput {
+                    parent_output: parent_hash,
+                    signature: Signature::default(),
+                }],
+                outputs: vec![
+                    TransactionOutput {
+                        value: Value::max_value(),
+                        pubkey: H256::from_slice(&ALICE_KEY),
+                        salt: 1,
+                        ..Default::default()
+                    },
+                    TransactionOutput {
+                        value: 10 as Value, // Attempts to do overflow total output value
+                        pubkey: H256::from_slice(&ALICE_KEY),
+                        salt: 1,
+                        ..Default::default()
+                    },
+                ],
+            });
+
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, transaction),
+                "output value overflow"
+            );
+        });
+    }
+
+    #[test]
+    fn attack_by_over_spending() {
+        with_externalities(&mut new_test_ext(), || {
+            let (parent_hash, _) = alice_utxo_100();
+
+            let transaction = signed(Transaction {
+                inputs: vec![TransactionInput {
+                    parent_output: parent_hash,
+                    signature: Signature::default(),
+                }],
+                outputs: vec![
+                    TransactionOutput {
+                        value: 100 as Value,
+                        pubkey: H256::from_slice(&ALICE_KEY),
+                        salt: 1,
+                        ..Default::default()
+                    },
+                    TransactionOutput {
+                        value: 1 as Value, // Creates 1 new utxo out of thin air!
+                        pubkey: H256::from_slice(&ALICE_KEY),
+                        salt: 1,
+                        ..Default::default()
+                    },
+                ],
+            });
+
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, transaction),
+                "output value must not exceed input value"
+            );
+        });
+    }
+    
+    #[test]
+    fn valid_transaction() {
+        with_externalities(&mut new_test_ext(), || {
+            let (parent_hash, _) = alice_utxo();
+
+            let transaction = Transaction {
+                inputs: vec![TransactionInput {
+                    parent_output: parent_hash,
+                    signature: Signature::from_slice(&ALICE_SIG),
+                }],
+                outputs: vec![TransactionOutput {
+                    value: 100,
+                    pubkey: H256::from_slice(&ALICE_KEY),
+                    salt: 2,
+                    ..Default::default()
+                }],
+            };
+            
+            let output_hash = BlakeTwo256::hash_of(&transaction.outputs[0]);
+
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction));
+            assert!(!<UnspentOutputs<Test>>::exists(parent_hash));
+            assert!(<UnspentOutputs<Test>>::exists(output_hash));
+            assert_indexes_consistent();
+        });
+    }
+
+    #[test]
+    fn fee_rate_accounts_for_transaction_size() {
+        with_externalities(&mut new_test_ext(), || {
+            let (parent_hash, _) = alice_utxo();
+            let input = TransactionInput {
+                parent_output: parent_hash,
+                signature: Signature::default(),
+            };
+
+            let small = signed(Transaction {
+                inputs: vec![input.clone()],
+                outputs: vec![TransactionOutput {
+                    value: 100,
+                    pubkey: H256::from_slice(&ALICE_KEY),
+                    salt: 2,
+                    ..Default::default()
+                }],
+            });
+
+            // Same fee as `small`, but split over two outputs
+            let large = signed(Transaction {
+                inputs: vec![input],
+                outputs: vec![
+                    TransactionOutput {
+                        value: 60,
+                        pubkey: H256::from_slice(&ALICE_KEY),
+                        salt: 2,
+                        ..Default::default()
+                    },
+                    TransactionOutput {
+                        value: 40,
+                        pubkey: H256::from_slice(&ALICE_KEY),
+                        salt: 3,
+                        ..Default::default()
+                    },
+                ],
+            });
+
+            let fee = Value::max_value() - 100;
+            assert_eq!(Utxo::fee_rate(&small), Some(fee / small.encode().len() as Value));
+            assert_eq!(Utxo::fee_rate(&large), Some(fee / large.encode().len() as Value));
+            assert!(Utxo::fee_rate(&small) > Utxo::fee_rate(&large));
+
+            // Unverifiable transactions have no fee rate
+            assert_eq!(Utxo::fee_rate(&Transaction::default()), None);
+        });
+    }
+
+    #[test]
+    fn upgrade_from_v0_converts_outputs_and_builds_owner_index() {
+        with_externalities(&mut new_test_ext_with(vec![]), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let old = |value, salt| TransactionOutputV0 { value, pubkey: alice, salt };
+
+            // Storage left behind by a version 0 runtime: a plain map of old outputs
+            <StorageVersion<Test>>::put(0);
+            let hashes = vec![BlakeTwo256::hash_of(&old(100, 0)), BlakeTwo256::hash_of(&old(7, 1))];
+            unhashed::put_raw(&Utxo::unspent_output_key(&hashes[0]), &old(100, 0).encode());
+            unhashed::put_raw(&Utxo::unspent_output_key(&hashes[1]), &old(7, 1).encode());
+
+            // Nothing happens until the old outputs are converted
+            run_to_block(1);
+            assert_eq!(Utxo::storage_version(), 0);
+
+            assert_err!(Utxo::migrate_outputs_v0(Origin::signed(1), hashes.clone(), true), "bad origin: expected to be a root origin");
+            assert_ok!(Utxo::migrate_outputs_v0(Origin::ROOT, vec![hashes[0], H256::random()], false));
+            assert_ok!(Utxo::migrate_outputs_v0(Origin::ROOT, vec![hashes[1]], true));
+            assert_eq!(Utxo::storage_version(), 1);
+            assert_err!(Utxo::migrate_outputs_v0(Origin::ROOT, hashes.clone(), true), "storage is not at version 0");
+
+            run_to_block(2);
+            assert_eq!(Utxo::storage_version(), CURRENT_STORAGE_VERSION);
+            assert_eq!(
+                <UnspentOutputs<Test>>::get(&hashes[0]),
+                Some(TransactionOutput { value: 100, pubkey: alice, salt: 0, ..Default::default() })
+            );
+            let mut owned = Utxo::owned_outputs(alice);
+            owned.sort();
+            let mut expected = hashes.clone();
+            expected.sort();
+            assert_eq!(owned, expected);
+            assert_eq!(<UnspentOutputs<Test>>::enumerate().count(), 2);
+            assert_eq!(Utxo::utxo_count(), 2);
+            assert_indexes_consistent();
+        });
+    }
+
+    #[test]
+    fn pool_priority_follows_fee_and_size() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            assert_ok!(Utxo::mint(Origin::signed(1), 10_000, alice));
+            let minted = BlakeTwo256::hash_of(&TransactionOutput { value: 10_000, pubkey: alice, salt: 0, ..Default::default() });
+
+            let spend = |outputs: Vec<Value>| {
+                let mut transaction = Transaction {
+                    inputs: vec![TransactionInput { parent_output: minted, signature: Signature::default() }],
+                    outputs: outputs
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, value)| TransactionOutput { value, pubkey: alice, salt: 10 + i as u64, ..Default::default() })
+                        .collect(),
+                };
+                sign_inputs(&mut transaction, &alice_pair());
+                transaction
+            };
+
+            let low_fee = spend(vec![8_000]);
+            let high_fee = spend(vec![6_000]);
+            let low_fee_large = spend(vec![4_000, 4_000]);
+
+            let low = Utxo::pool_priority(&low_fee).unwrap();
+            assert_eq!(low, 2_000 / low_fee.encode().len() as u64);
+            assert!(Utxo::pool_priority(&high_fee).unwrap() > low);
+            assert!(Utxo::pool_priority(&low_fee_large).unwrap() < low);
+
+            // Huge fees are clamped
+            let (parent_hash, _) = alice_utxo();
+            let whale = Transaction {
+                inputs: vec![TransactionInput { parent_output: parent_hash, signature: Signature::from_slice(&ALICE_SIG) }],
+                outputs: vec![TransactionOutput { value: 100, pubkey: alice, salt: 2, ..Default::default() }],
+            };
+            assert_eq!(Utxo::pool_priority(&whale), Ok(u64::max_value()));
+
+            // Transactions waiting for inputs rank last, invalid ones are rejected
+            let orphan = Transaction {
+                inputs: vec![TransactionInput { parent_output: H256::random(), signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 100, pubkey: alice, salt: 2, ..Default::default() }],
+            };
+            assert_eq!(Utxo::pool_priority(&orphan), Ok(0));
+            assert_eq!(Utxo::pool_priority(&Transaction::default()), Err("no inputs"));
+        });
+    }
+
+    #[test]
+    fn pool_tags_link_child_to_parent() {
+        with_externalities(&mut new_test_ext(), || {
+            let (parent_hash, _) = alice_utxo();
+            let alice = H256::from_slice(&ALICE_KEY);
+
+            let parent = Transaction {
+                inputs: vec![TransactionInput {
+                    parent_output: parent_hash,
+                    signature: Signature::from_slice(&ALICE_SIG),
+                }],
+                outputs: vec![TransactionOutput { value: 100, pubkey: alice, salt: 2, ..Default::default() }],
+            };
+
+            let child = Transaction {
+                inputs: vec![TransactionInput {
+                    parent_output: BlakeTwo256::hash_of(&parent.outputs[0]),
+                    signature: Signature::default(),
+                }],
+                outputs: vec![TransactionOutput { value: 50, pubkey: alice, salt: 3, ..Default::default() }],
+            };
+
+            let (parent_requires, parent_provides) = Utxo::pool_tags(&parent);
+            let (child_requires, child_provides) = Utxo::pool_tags(&child);
+
+            assert!(parent_requires.is_empty());
+            assert_eq!(child_requires, parent_provides);
+            assert_eq!(child_provides, vec![BlakeTwo256::hash_of(&child.outputs[0]).as_fixed_bytes().to_vec()]);
+        });
+    }
+
+    #[test]
+    fn tiny_leftover_rotates_between_authorities() {
+        with_externalities(&mut new_test_ext(), || {
+            let authorities = vec![H256::random(), H256::random(), H256::random()];
+
+            for block in 1..=4 {
+                <system::Module<Test>>::set_block_number(block);
+                <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 2);
+
+                Utxo::spend_leftover(&authorities);
+
+                let authority = authorities[(block as usize - 1) % authorities.len()];
+                let expected = TransactionOutput {
+                    value: 2,
+                    pubkey: authority,
+                    salt: Utxo::leftover_salt(&authority, NATIVE_ASSET, 2),
+                    ..Default::default()
+                };
+                assert!(<UnspentOutputs<Test>>::exists(BlakeTwo256::hash_of(&expected)));
+                assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 0);
+            }
+
+            assert_eq!(Utxo::leftover_cursor(), 4);
+        });
+    }
+
+    #[test]
+    fn same_effect_ignores_order_and_signatures() {
+        let alice = H256::from_slice(&ALICE_KEY);
+        let input = |parent_output: H256| TransactionInput { parent_output, signature: H512::random() };
+        let output = |value: Value| TransactionOutput { value, pubkey: alice, salt: 0, ..Default::default() };
+        let (first, second) = (H256::random(), H256::random());
+
+        let transaction = Transaction {
+            inputs: vec![input(first), input(second)],
+            outputs: vec![output(10), output(20)],
+        };
+
+        // Same inputs and outputs listed in a different order
+        let reordered = Transaction {
+            inputs: vec![transaction.inputs[1].clone(), transaction.inputs[0].clone()],
+            outputs: vec![output(20), output(10)],
+        };
+        assert!(Utxo::same_effect(&transaction, &reordered));
+
+        // Same inputs, but with other signatures
+        let resigned = Transaction {
+            inputs: vec![input(first), input(second)],
+            outputs: transaction.outputs.clone(),
+        };
+        assert!(Utxo::same_effect(&transaction, &resigned));
+
+        // Genuinely different transactions
+        let other_output = Transaction {
+            inputs: transaction.inputs.clone(),
+            outputs: vec![output(10), output(21)],
+        };
+        let other_input = Transaction {
+            inputs: vec![input(first)],
+            outputs: transaction.outputs.clone(),
+        };
+        assert!(!Utxo::same_effect(&transaction, &other_output));
+        assert!(!Utxo::same_effect(&transaction, &other_input));
+    }
+
+    #[test]
+    fn mint_retries_with_next_salt_on_collision() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let minted = |salt| TransactionOutput { value: 5, pubkey: alice, salt, ..Default::default() };
+
+            // Occupy the hash the first attempt would produce
+            <UnspentOutputs<Test>>::insert(BlakeTwo256::hash_of(&minted(0)), minted(0));
+
+            assert_ok!(Utxo::mint(Origin::signed(1), 5, alice));
+            assert!(<UnspentOutputs<Test>>::exists(BlakeTwo256::hash_of(&minted(1))));
+
+            // Give up once every attempt collides
+            for salt in 0..MINT_ATTEMPTS {
+                <UnspentOutputs<Test>>::insert(BlakeTwo256::hash_of(&minted(salt)), minted(salt));
+            }
+            assert_err!(Utxo::mint(Origin::signed(1), 5, alice), "cannot mint due to hash collision");
+        });
+    }
+
+    // Inserts an escrow output paying 100 to Bob, refundable to Alice from block 10
+    fn refundable_utxo() -> H256 {
+        let utxo = TransactionOutput {
+            value: 100,
+            pubkey: H256::from_slice(bob_pair().public().as_ref()),
+            salt: 0,
+            condition: SpendCondition::Refundable {
+                refund_to: H256::from_slice(&ALICE_KEY),
+                deadline: 10,
+            },
+            asset_id: NATIVE_ASSET,
+            expires_at: None,
+        };
+        let hash = BlakeTwo256::hash_of(&utxo);
+        Utxo::insert_utxo(hash, utxo);
+        hash
+    }
+
+    // Builds a transaction moving the escrow value to a plain output of the signer
+    fn claim_refundable(parent_output: H256, pair: &sr25519::Pair) -> Transaction {
+        let mut transaction = Transaction {
+            inputs: vec![TransactionInput { parent_output, signature: Signature::default() }],
+            outputs: vec![TransactionOutput {
+                value: 100,
+                pubkey: H256::from_slice(pair.public().as_ref()),
+                salt: 1,
+                ..Default::default()
+            }],
+        };
+        sign_inputs(&mut transaction, pair);
+        transaction
+    }
+
+    #[test]
+    fn refundable_output_claimed_by_recipient_before_deadline() {
+        with_externalities(&mut new_test_ext(), || {
+            let escrow = refundable_utxo();
+            <system::Module<Test>>::set_block_number(9);
+
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, claim_refundable(escrow, &alice_pair())),
+                "signature must be valid"
+            );
+            assert_ok!(Utxo::execute(Origin::INHERENT, claim_refundable(escrow, &bob_pair())));
+            assert!(!<UnspentOutputs<Test>>::exists(escrow));
+        });
+    }
+
+    #[test]
+    fn refundable_output_reclaimed_by_sender_after_deadline() {
+        with_externalities(&mut new_test_ext(), || {
+            let escrow = refundable_utxo();
+            <system::Module<Test>>::set_block_number(10);
+
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, claim_refundable(escrow, &bob_pair())),
+                "signature must be valid"
+            );
+            assert_ok!(Utxo::execute(Origin::INHERENT, claim_refundable(escrow, &alice_pair())));
+            assert!(!<UnspentOutputs<Test>>::exists(escrow));
+        });
+    }
+
+    #[test]
+    fn genesis_audit_detects_colliding_outputs() {
+        with_externalities(&mut new_test_ext(), || {
+            assert_ok!(Utxo::audit_genesis());
+        });
+
+        // The second output lands on the same hash and overwrites the first
+        with_externalities(&mut new_test_ext_with(vec![alice_utxo_100().1, alice_utxo_100().1]), || {
+            assert_eq!(Utxo::genesis_utxo_count(), 2);
+            assert_err!(Utxo::audit_genesis(), "genesis utxo count mismatch");
+        });
+    }
+
+    #[test]
+    fn genesis_audit_failure_reported_at_first_block() {
+        with_externalities(&mut new_test_ext(), || {
+            run_to_block(1);
+            assert!(!utxo_events().iter().any(|event| match event {
+                Event::GenesisAuditFailed(..) => true,
+                _ => false,
+            }));
+        });
+
+        with_externalities(&mut new_test_ext_with(vec![alice_utxo_100().1, alice_utxo_100().1]), || {
+            run_to_block(1);
+            assert!(utxo_events().contains(&Event::GenesisAuditFailed(2, 1)));
+
+            // Only audited before anything else touches the UTXO set
+            run_to_block(2);
+            assert_eq!(utxo_events().iter().filter(|event| **event == Event::GenesisAuditFailed(2, 1)).count(), 1);
+        });
+    }
+
+    #[test]
+    fn spend_counts_follow_transfers() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let bob = H256::from_slice(bob_pair().public().as_ref());
+
+            // Alice pays Bob and keeps the change
+            let (parent_hash, _) = alice_utxo();
+            let payment = TransactionOutput { value: 100, pubkey: bob, salt: 2, ..Default::default() };
+            assert_ok!(Utxo::execute(
+                Origin::INHERENT,
+                signed(Transaction {
+                    inputs: vec![TransactionInput {
+                        parent_output: parent_hash,
+                        signature: Signature::default(),
+                    }],
+                    outputs: vec![
+                        payment.clone(),
+                        TransactionOutput { value: 1_000, pubkey: alice, salt: 2, ..Default::default() },
+                    ],
+                })
+            ));
+
+            // Bob pays Alice back
+            let mut refund = Transaction {
+                inputs: vec![TransactionInput { parent_output: BlakeTwo256::hash_of(&payment), signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 100, pubkey: alice, salt: 3, ..Default::default() }],
+            };
+            sign_inputs(&mut refund, &bob_pair());
+            assert_ok!(Utxo::execute(Origin::INHERENT, refund));
+
+            assert_eq!(Utxo::spent_count(alice), 1);
+            assert_eq!(Utxo::received_count(alice), 2);
+            assert_eq!(Utxo::spent_count(bob), 1);
+            assert_eq!(Utxo::received_count(bob), 1);
+        });
+    }
+
+    #[test]
+    fn unlock_utxo_only_unlocks_locked_outputs() {
+        with_externalities(&mut new_test_ext(), || {
+            let (hash, _) = alice_utxo_100();
+            assert_err!(Utxo::unlock_utxo(&hash), "utxo is not locked");
+
+            assert_ok!(Utxo::lock_utxo(&hash, None));
+            assert_ok!(Utxo::unlock_utxo(&hash));
+            assert!(!<LockedOutputs<Test>>::exists(hash));
+        });
+    }
+
+    #[test]
+    fn owner_can_cancel_time_lock_early() {
+        with_externalities(&mut new_test_ext(), || {
+            let (hash, _) = alice_utxo_100();
+            assert_ok!(Utxo::lock_utxo(&hash, Some(10)));
+
+            let payload = Utxo::cancel_lock_payload(&hash);
+            let owner_sig = Signature::from_slice(&alice_pair().sign(&payload).0);
+
+            assert_ok!(Utxo::cancel_lock(Origin::signed(1), hash, owner_sig));
+            assert!(!<LockedOutputs<Test>>::exists(hash));
+            assert_eq!(utxo_events(), vec![Event::OutputUnlocked(hash)]);
+
+            // The signature can't cancel a later lock, even once forgotten by the recent signatures
+            assert_ok!(Utxo::lock_utxo(&hash, Some(20)));
+            assert_err!(Utxo::cancel_lock(Origin::signed(1), hash, owner_sig), "signature recently used");
+            <RecentSignatures<Test>>::remove(owner_sig);
+            assert_err!(Utxo::cancel_lock(Origin::signed(1), hash, owner_sig), "signature must be valid");
+            assert!(<LockedOutputs<Test>>::exists(hash));
+        });
+    }
+
+    #[test]
+    fn time_lock_cancel_requires_owner_signature() {
+        with_externalities(&mut new_test_ext(), || {
+            let (hash, _) = alice_utxo_100();
+            assert_ok!(Utxo::lock_utxo(&hash, Some(10)));
+
+            let payload = Utxo::cancel_lock_payload(&hash);
+            let stranger_sig = Signature::from_slice(&bob_pair().sign(&payload).0);
+
+            assert_err!(
+                Utxo::cancel_lock(Origin::signed(1), hash, stranger_sig),
+                "signature must be valid"
+            );
+            assert!(<LockedOutputs<Test>>::exists(hash));
+
+            // A spend authorization can't be replayed to cancel the lock either
+            assert_err!(
+                Utxo::cancel_lock(Origin::signed(1), hash, Signature::from_slice(&ALICE_SIG100)),
+                "signature must be valid"
+            );
+        });
+    }
+
+    #[test]
+    fn spendable_at_respects_lock_deadlines() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let (vested, vested_utxo) = alice_utxo();
+            let (free, free_utxo) = alice_utxo_100();
+            assert_ok!(Utxo::mint(Origin::signed(1), 7, alice));
+            let frozen = BlakeTwo256::hash_of(&TransactionOutput { value: 7, pubkey: alice, salt: 0, ..Default::default() });
+
+            assert_ok!(Utxo::lock_utxo(&vested, Some(10)));
+            assert_ok!(Utxo::lock_utxo(&frozen, None));
+
+            let sorted = |mut outputs: Vec<(H256, TransactionOutput)>| {
+                outputs.sort();
+                outputs
+            };
+            let both = sorted(vec![(vested, vested_utxo), (free, free_utxo.clone())]);
+
+            assert_eq!(Utxo::spendable_at(&alice, 9), vec![(free, free_utxo)]);
+            assert_eq!(sorted(Utxo::spendable_at(&alice, 10)), both);
+            assert_eq!(sorted(Utxo::spendable_at(&alice, 20)), both);
+            assert!(Utxo::spendable_at(&H256::random(), 20).is_empty());
+        });
+    }
+
+    #[test]
+    fn time_lock_expires_exactly_at_deadline() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let (hash, utxo) = alice_utxo_100();
+            let now = || <system::Module<Test>>::block_number();
+
+            run_to_block(1);
+            assert_ok!(Utxo::lock_utxo(&hash, Some(5)));
+
+            run_to_block(4);
+            assert!(!Utxo::spendable_at(&alice, now()).contains(&(hash, utxo.clone())));
+
+            run_to_block(5);
+            assert!(Utxo::spendable_at(&alice, now()).contains(&(hash, utxo)));
+            assert_err!(Utxo::lock_utxo(&alice_utxo().0, Some(5)), "block number is in the past");
+        });
+    }
+
+    #[test]
+    fn malleated_transactions_normalize_identically() {
+        let alice = H256::from_slice(&ALICE_KEY);
+        let input = |parent_output: H256| TransactionInput { parent_output, signature: H512::random() };
+        let (first, second) = (H256::random(), H256::random());
+        let outputs = vec![TransactionOutput { value: 10, pubkey: alice, salt: 0, ..Default::default() }];
+
+        let transaction = Transaction { inputs: vec![input(first), input(second)], outputs: outputs.clone() };
+        let malleated = Transaction { inputs: vec![input(second), input(first)], outputs };
+        assert_ne!(transaction, malleated);
+
+        let normalized = Utxo::normalize(&transaction);
+        assert_eq!(normalized, Utxo::normalize(&malleated));
+        assert!(normalized.inputs.iter().all(|input| input.signature == Signature::default()));
+        assert!(normalized.inputs[0].parent_output < normalized.inputs[1].parent_output);
+    }
+
+    #[test]
+    fn reordered_transactions_encode_canonically() {
+        let alice = H256::from_slice(&ALICE_KEY);
+        let input = |parent_output: H256| TransactionInput { parent_output, signature: H512::random() };
+        let output = |value| TransactionOutput { value, pubkey: alice, salt: 0, ..Default::default() };
+        let (first, second) = (H256::random(), H256::random());
+
+        let transaction = Transaction { inputs: vec![input(first), input(second)], outputs: vec![output(10), output(20)] };
+        let reordered = Transaction { inputs: vec![input(second), input(first)], outputs: vec![output(20), output(10)] };
+        assert_ne!(transaction.encode(), reordered.encode());
+
+        assert_eq!(Utxo::canonical_encode(&transaction), Utxo::canonical_encode(&reordered));
+        assert_eq!(Utxo::txid(&transaction), Utxo::txid(&reordered));
+    }
+
+    #[test]
+    fn block_tx_count_resets_every_block() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let spend = |parent_output, salt| signed(Transaction {
+                inputs: vec![TransactionInput { parent_output, signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 50, pubkey: alice, salt, ..Default::default() }],
+            });
+
+            run_to_block(1);
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(alice_utxo().0, 2)));
+            assert_eq!(Utxo::block_tx_count(), 1);
+
+            // Failed transactions are not counted
+            assert!(Utxo::execute(Origin::INHERENT, spend(alice_utxo().0, 2)).is_err());
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(alice_utxo_100().0, 3)));
+            assert_eq!(Utxo::block_tx_count(), 2);
+
+            run_to_block(2);
+            assert_eq!(Utxo::block_tx_count(), 0);
+        });
+    }
+
+    #[test]
+    fn execute_rejects_transactions_over_block_cap() {
+        with_externalities(&mut new_test_ext(), || {
+            <MaxTxPerBlock<Test>>::put(1);
+            let alice = H256::from_slice(&ALICE_KEY);
+            let spend = |parent_output, salt| signed(Transaction {
+                inputs: vec![TransactionInput { parent_output, signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 50, pubkey: alice, salt, ..Default::default() }],
+            });
+
+            run_to_block(1);
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(alice_utxo().0, 2)));
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, spend(alice_utxo_100().0, 3)),
+                "block transaction limit reached"
+            );
+
+            // The cap applies per block
+            run_to_block(2);
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(alice_utxo_100().0, 3)));
+        });
+    }
+
+    #[test]
+    fn output_hash_matches_genesis_storage() {
+        with_externalities(&mut new_test_ext(), || {
+            for (_, output) in vec![alice_utxo(), alice_utxo_100()] {
+                let hash = Utxo::output_hash(&output);
+                assert_eq!(<UnspentOutputs<Test>>::get(hash), Some(output));
+                assert!(Utxo::owned_outputs(H256::from_slice(&ALICE_KEY)).contains(&hash));
+            }
+        });
+    }
+
+    // Inserts an output of 100 owned by Alice, recoverable by Bob 5 blocks after creation
+    fn recoverable_utxo() -> H256 {
+        let utxo = TransactionOutput {
+            value: 100,
+            pubkey: H256::from_slice(&ALICE_KEY),
+            salt: 0,
+            condition: SpendCondition::Recoverable {
+                backup: H256::from_slice(bob_pair().public().as_ref()),
+                delay: 5,
+            },
+            asset_id: NATIVE_ASSET,
+            expires_at: None,
+        };
+        let hash = Utxo::output_hash(&utxo);
+        Utxo::insert_utxo(hash, utxo);
+        hash
+    }
+
+    #[test]
+    fn recoverable_output_spent_by_primary_key_at_any_time() {
+        with_externalities(&mut new_test_ext(), || {
+            run_to_block(1);
+            let hash = recoverable_utxo();
+
+            assert_ok!(Utxo::execute(Origin::INHERENT, claim_refundable(hash, &alice_pair())));
+            assert!(!<CreatedAt<Test>>::exists(hash));
+        });
+    }
+
+    #[test]
+    fn recoverable_output_rejects_backup_key_before_delay() {
+        with_externalities(&mut new_test_ext(), || {
+            run_to_block(1);
+            let hash = recoverable_utxo();
+
+            run_to_block(5);
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, claim_refundable(hash, &bob_pair())),
+                "signature must be valid"
+            );
+        });
+    }
+
+    #[test]
+    fn recoverable_output_spent_by_backup_key_after_delay() {
+        with_externalities(&mut new_test_ext(), || {
+            run_to_block(1);
+            let hash = recoverable_utxo();
+
+            run_to_block(6);
+            assert_ok!(Utxo::execute(Origin::INHERENT, claim_refundable(hash, &bob_pair())));
+            assert!(!<UnspentOutputs<Test>>::exists(hash));
+        });
+    }
+
+    #[test]
+    fn resolve_inputs_returns_spent_outputs_in_order() {
+        with_externalities(&mut new_test_ext(), || {
+            let input = |parent_output| TransactionInput { parent_output, signature: Signature::default() };
+            let mut transaction = Transaction {
+                inputs: vec![input(alice_utxo_100().0), input(alice_utxo().0)],
+                outputs: vec![],
+            };
+
+            assert_eq!(
+                Utxo::resolve_inputs(&transaction),
+                Ok(vec![alice_utxo_100().1, alice_utxo().1])
+            );
+
+            transaction.inputs.push(input(H256::random()));
+            assert_eq!(Utxo::resolve_inputs(&transaction), Err("missing input utxo"));
+        });
+    }
+
+    #[test]
+    fn leftover_shares_are_unique_across_blocks() {
+        with_externalities(&mut new_test_ext(), || {
+            let authorities = vec![H256::random(), H256::random()];
+            let mut shares = Vec::new();
+
+            for block in 1..=2 {
+                <system::Module<Test>>::set_block_number(block);
+                <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 10);
+                Utxo::spend_leftover(&authorities);
+
+                for authority in authorities.iter() {
+                    shares.extend(Utxo::owned_outputs(authority));
+                }
+            }
+
+            shares.sort();
+            shares.dedup();
+            assert_eq!(shares.len(), 4);
+            assert!(shares.iter().all(|hash| <UnspentOutputs<Test>>::get(hash).map(|utxo| utxo.value) == Some(5)));
+        });
+    }
+
+    #[test]
+    fn frozen_account_cannot_spend() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let bob = H256::from_slice(bob_pair().public().as_ref());
+            let spend = |parent_output, pair: &sr25519::Pair, salt| {
+                let mut transaction = Transaction {
+                    inputs: vec![TransactionInput { parent_output, signature: Signature::default() }],
+                    outputs: vec![TransactionOutput { value: 10, pubkey: bob, salt, ..Default::default() }],
+                };
+                sign_inputs(&mut transaction, pair);
+                transaction
+            };
+
+            assert_ok!(Utxo::mint(Origin::signed(1), 10, bob));
+            let bob_utxo = Utxo::owned_outputs(bob)[0];
+
+            assert_err!(Utxo::freeze_account(Origin::signed(1), alice), "bad origin: expected to be a root origin");
+            assert_ok!(Utxo::freeze_account(Origin::ROOT, alice));
+            assert_eq!(utxo_events(), vec![Event::AccountFrozen(alice)]);
+
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, spend(alice_utxo_100().0, &alice_pair(), 1)),
+                "account is frozen"
+            );
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(bob_utxo, &bob_pair(), 2)));
+
+            assert_ok!(Utxo::unfreeze_account(Origin::ROOT, alice));
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(alice_utxo_100().0, &alice_pair(), 1)));
+        });
+    }
+
+    #[test]
+    fn transaction_executed_event_describes_effect() {
+        with_externalities(&mut new_test_ext(), || {
+            let (parent_hash, _) = alice_utxo_100();
+            let transaction = signed(Transaction {
+                inputs: vec![TransactionInput {
+                    parent_output: parent_hash,
+                    signature: Signature::default(),
+                }],
+                outputs: vec![TransactionOutput {
+                    value: 90,
+                    pubkey: H256::from_slice(&ALICE_KEY),
+                    salt: 2,
+                    ..Default::default()
+                }],
+            });
+            let created = Utxo::output_hash(&transaction.outputs[0]);
+
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction.clone()));
+            assert!(!<UnspentOutputs<Test>>::exists(parent_hash));
+            assert!(<UnspentOutputs<Test>>::exists(created));
+            assert_eq!(
+                utxo_events(),
+                vec![
+                    Event::OutputCreated(H256::from_slice(&ALICE_KEY), created),
+                    Event::TransactionExecuted(Utxo::txid(&transaction), vec![parent_hash], vec![created], 10),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn leftover_held_without_authorities() {
+        with_externalities(&mut new_test_ext(), || {
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 7);
+            Utxo::spend_leftover(&[]);
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 7);
+        });
+    }
+
+    #[test]
+    fn leftover_burnt_without_authorities() {
+        with_externalities(&mut new_test_ext(), || {
+            <NoAuthorityPolicy<Test>>::put(LeftoverPolicy::Burn);
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 7);
+
+            Utxo::spend_leftover(&[]);
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 0);
+            assert_eq!(Utxo::stored_utxo_count(), 2);
+        });
+    }
+
+    #[test]
+    fn leftover_credited_to_fallback_without_authorities() {
+        with_externalities(&mut new_test_ext(), || {
+            let fallback = H256::random();
+            <NoAuthorityPolicy<Test>>::put(LeftoverPolicy::Credit(fallback));
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 7);
+
+            Utxo::spend_leftover(&[]);
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 0);
+
+            let credited = Utxo::owned_outputs(fallback);
+            assert_eq!(credited.len(), 1);
+            assert_eq!(<UnspentOutputs<Test>>::get(credited[0]).map(|utxo| utxo.value), Some(7));
+        });
+    }
+
+    #[test]
+    fn mint_salt_wraps_at_largest_block_number() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let minted = |salt| TransactionOutput { value: 5, pubkey: alice, salt, ..Default::default() };
+            <system::Module<Test>>::set_block_number(u64::max_value());
+            assert_eq!(Utxo::block_salt(), u64::max_value());
+
+            assert_ok!(Utxo::mint(Origin::signed(1), 5, alice));
+            assert_ok!(Utxo::mint(Origin::signed(1), 5, alice));
+            assert!(<UnspentOutputs<Test>>::exists(Utxo::output_hash(&minted(u64::max_value()))));
+            assert!(<UnspentOutputs<Test>>::exists(Utxo::output_hash(&minted(0))));
+        });
+    }
+
+    #[test]
+    fn events_are_indexed_by_owner_and_txid() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let bob = H256::from_slice(bob_pair().public().as_ref());
+            let transaction = signed(Transaction {
+                inputs: vec![TransactionInput {
+                    parent_output: alice_utxo_100().0,
+                    signature: Signature::default(),
+                }],
+                outputs: vec![
+                    TransactionOutput { value: 60, pubkey: bob, salt: 2, ..Default::default() },
+                    TransactionOutput { value: 40, pubkey: alice, salt: 2, ..Default::default() },
+                ],
+            });
+            let txid = Utxo::txid(&transaction);
+
+            run_to_block(1);
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction.clone()));
+
+            let events = utxo_events();
+            let event_at = |index: u32| events[index as usize].clone();
+            assert_eq!(Utxo::event_topics(bob).into_iter().map(event_at).collect::<Vec<_>>(),
+                vec![Event::OutputCreated(bob, Utxo::output_hash(&transaction.outputs[0]))]);
+            assert_eq!(Utxo::event_topics(alice).into_iter().map(event_at).collect::<Vec<_>>(),
+                vec![Event::OutputCreated(alice, Utxo::output_hash(&transaction.outputs[1]))]);
+            match event_at(Utxo::event_topics(txid)[0]) {
+                Event::TransactionExecuted(id, ..) => assert_eq!(id, txid),
+                other => panic!("unexpected event {:?}", other),
+            }
+
+            // Topics only cover the current block
+            run_to_block(2);
+            assert!(Utxo::event_topics(alice).is_empty());
+            assert!(Utxo::event_topics(txid).is_empty());
+        });
+    }
+
+    #[test]
+    fn confirmations_count_blocks_since_creation() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            run_to_block(3);
+            assert_ok!(Utxo::mint(Origin::signed(1), 5, alice));
+            let minted = Utxo::output_hash(&TransactionOutput { value: 5, pubkey: alice, salt: 3, ..Default::default() });
+
+            assert_eq!(Utxo::confirmations(&minted, 3), Some(0));
+            assert_eq!(Utxo::confirmations(&minted, 10), Some(7));
+            assert_eq!(Utxo::confirmations(&minted, 2), None);
+
+            // Genesis outputs carry no creation block, unknown ones don't exist
+            assert_eq!(Utxo::confirmations(&alice_utxo().0, 10), None);
+            assert_eq!(Utxo::confirmations(&H256::random(), 10), None);
+        });
+    }
+
+    // Spends the given outputs into a single output of 10 to Alice, signing with each owner in turn
+    fn spend_jointly(parents: &[(H256, &sr25519::Pair)]) -> Transaction {
+        let mut transaction = Transaction {
+            inputs: vec![],
+            outputs: vec![TransactionOutput { value: 10, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() }],
+        };
+        for (parent_output, _) in parents {
+            transaction.inputs.push(TransactionInput { parent_output: *parent_output, signature: Signature::default() });
+        }
+        let payload = Utxo::signing_payload(&transaction);
+        for (input, (_, pair)) in transaction.inputs.iter_mut().zip(parents) {
+            input.signature = Signature::from_slice(&pair.sign(&payload).0);
+        }
+        transaction
+    }
+
+    #[test]
+    fn single_owner_tx_allows_inputs_of_one_owner() {
+        with_externalities(&mut new_test_ext(), || {
+            <SingleOwnerTx<Test>>::put(true);
+            let alice = alice_pair();
+
+            assert_ok!(Utxo::execute(
+                Origin::INHERENT,
+                spend_jointly(&[(alice_utxo().0, &alice), (alice_utxo_100().0, &alice)])
+            ));
+        });
+    }
+
+    #[test]
+    fn single_owner_tx_rejects_inputs_of_two_owners() {
+        with_externalities(&mut new_test_ext(), || {
+            let (alice, bob) = (alice_pair(), bob_pair());
+            assert_ok!(Utxo::mint(Origin::signed(1), 5, H256::from_slice(bob.public().as_ref())));
+            let bob_utxo = Utxo::owned_outputs(H256::from_slice(bob.public().as_ref()))[0];
+            let transaction = spend_jointly(&[(alice_utxo_100().0, &alice), (bob_utxo, &bob)]);
+
+            <SingleOwnerTx<Test>>::put(true);
+            assert_err!(Utxo::execute(Origin::INHERENT, transaction.clone()), "mixed-owner inputs not allowed");
+
+            <SingleOwnerTx<Test>>::put(false);
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction));
+        });
+    }
+
+    #[test]
+    fn expected_genesis_root_matches_built_storage() {
+        let utxos = vec![alice_utxo().1, alice_utxo_100().1];
+
+        with_externalities(&mut new_test_ext(), || {
+            let stored: BTreeMap<_, _> = <UnspentOutputs<Test>>::enumerate()
+                .map(|(hash, _)| <UnspentOutputs<Test>>::key_for(hash))
+                .map(|key| {
+                    let value = runtime_io::storage(&key).expect("key was just enumerated");
+                    (key, value)
+                })
+                .collect();
+
+            assert_eq!(Utxo::expected_genesis_root(&utxos), BlakeTwo256::trie_root(stored));
+            assert_ne!(Utxo::expected_genesis_root(&utxos), Utxo::expected_genesis_root(&utxos[..1]));
+        });
+    }
+
+    #[test]
+    fn first_input_error_reports_failing_index() {
+        with_externalities(&mut new_test_ext(), || {
+            let (alice, bob) = (alice_pair(), bob_pair());
+            let bob_key = H256::from_slice(bob.public().as_ref());
+            assert_ok!(Utxo::mint(Origin::signed(1), 5, bob_key));
+            let bob_utxo = Utxo::owned_outputs(bob_key)[0];
+            let error = |index, reason| Some(InputError { index, reason });
+
+            let valid = spend_jointly(&[(bob_utxo, &bob), (alice_utxo().0, &alice)]);
+            assert_eq!(Utxo::first_input_error(&valid), None);
+
+            let missing = spend_jointly(&[(bob_utxo, &bob), (H256::random(), &alice)]);
+            assert_eq!(Utxo::first_input_error(&missing), error(1, InputFailure::Missing));
+
+            let forged = spend_jointly(&[(bob_utxo, &bob), (alice_utxo().0, &bob)]);
+            assert_eq!(Utxo::first_input_error(&forged), error(1, InputFailure::BadSignature));
+            assert_err!(Utxo::execute(Origin::INHERENT, forged), "signature must be valid");
+
+            <SingleOwnerTx<Test>>::put(true);
+            assert_eq!(Utxo::first_input_error(&valid), error(1, InputFailure::MixedOwner));
+            <SingleOwnerTx<Test>>::put(false);
+
+            assert_ok!(Utxo::freeze_account(Origin::ROOT, H256::from_slice(&ALICE_KEY)));
+            assert_eq!(Utxo::first_input_error(&valid), error(1, InputFailure::Frozen));
+
+            assert_ok!(Utxo::lock_utxo(&bob_utxo, None));
+            assert_eq!(Utxo::first_input_error(&valid), error(0, InputFailure::Locked));
+        });
+    }
+
+    #[test]
+    fn assets_balance_independently() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let gold = |value, salt| TransactionOutput { value, pubkey: alice, salt, asset_id: 1, ..Default::default() };
+            let gold_utxo = Utxo::output_hash(&gold(50, 0));
+            Utxo::insert_utxo(gold_utxo, gold(50, 0));
+
+            let two_assets = |native: Value, gold_value: Value| {
+                let mut transaction = Transaction {
+                    inputs: vec![
+                        TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() },
+                        TransactionInput { parent_output: gold_utxo, signature: Signature::default() },
+                    ],
+                    outputs: vec![
+                        TransactionOutput { value: native, pubkey: alice, salt: 1, ..Default::default() },
+                        gold(gold_value, 1),
+                    ],
+                };
+                sign_inputs(&mut transaction, &alice_pair());
+                transaction
+            };
+
+            // Surplus of one asset can't cover a deficit of another
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, two_assets(40, 60)),
+                "output value must not exceed input value"
+            );
+
+            assert_ok!(Utxo::execute(Origin::INHERENT, two_assets(90, 50)));
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 10);
+            assert_eq!(Utxo::leftover_total(1), 0);
+            assert!(<UnspentOutputs<Test>>::exists(Utxo::output_hash(&gold(50, 1))));
+        });
+    }
+
+    #[test]
+    fn leftover_pooled_and_shared_per_asset() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let gold = |value, salt| TransactionOutput { value, pubkey: alice, salt, asset_id: 1, ..Default::default() };
+            let gold_utxo = Utxo::output_hash(&gold(50, 0));
+            Utxo::insert_utxo(gold_utxo, gold(50, 0));
+
+            let mut transaction = Transaction {
+                inputs: vec![
+                    TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() },
+                    TransactionInput { parent_output: gold_utxo, signature: Signature::default() },
+                ],
+                outputs: vec![
+                    TransactionOutput { value: 90, pubkey: alice, salt: 1, ..Default::default() },
+                    gold(30, 1),
+                ],
+            };
+            sign_inputs(&mut transaction, &alice_pair());
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction));
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 10);
+            assert_eq!(Utxo::leftover_total(1), 20);
+
+            let authorities = vec![H256::random(), H256::random()];
+            Utxo::spend_leftover(&authorities);
+
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 0);
+            assert_eq!(Utxo::leftover_total(1), 0);
+            for authority in authorities.iter() {
+                let mut shares: Vec<_> = Utxo::owned_outputs(authority)
+                    .into_iter()
+                    .filter_map(|hash| <UnspentOutputs<Test>>::get(hash))
+                    .map(|utxo| (utxo.asset_id, utxo.value))
+                    .collect();
+                shares.sort();
+                assert_eq!(shares, vec![(NATIVE_ASSET, 5), (1, 10)]);
+            }
+        });
+    }
+
+    #[test]
+    fn colliding_asset_share_gets_own_salt() {
+        with_externalities(&mut new_test_ext(), || {
+            let authority = H256::random();
+            run_to_block(1);
+
+            let (target, share) = Utxo::leftover_share(authority, 1, 7);
+            Utxo::insert_utxo(target, share.clone());
+
+            <LeftoverTotals<Test>>::insert(1, 7);
+            Utxo::spend_leftover(&[authority]);
+
+            assert_eq!(<UnspentOutputs<Test>>::get(target), Some(share.clone()));
+            let salted = TransactionOutput { salt: 1, ..share };
+            assert!(<UnspentOutputs<Test>>::exists(Utxo::output_hash(&salted)));
+            assert_eq!(Utxo::leftover_total(1), 0);
+            assert_eq!(Utxo::claimable_rewards(&authority), 0);
+            assert_indexes_consistent();
+        });
+    }
+
+    #[test]
+    fn upgrade_to_v2_moves_pending_leftover_to_native_pool() {
+        with_externalities(&mut new_test_ext(), || {
+            <StorageVersion<Test>>::put(1);
+            <LeftoverTotal<Test>>::put(5);
+
+            Utxo::on_runtime_upgrade();
+
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 5);
+            assert!(!<LeftoverTotal<Test>>::exists());
+            assert_eq!(Utxo::storage_version(), CURRENT_STORAGE_VERSION);
+        });
+    }
+
+    #[test]
+    fn lock_utxo_rejects_locks_beyond_max_duration() {
+        with_externalities(&mut new_test_ext(), || {
+            <MaxLockDuration<Test>>::put(100);
+            run_to_block(1);
+
+            assert_err!(Utxo::lock_utxo(&alice_utxo().0, Some(102)), "lock duration too long");
+            assert_ok!(Utxo::lock_utxo(&alice_utxo().0, Some(101)));
+
+            // Indefinite locks are not time-locks and stay allowed
+            assert_ok!(Utxo::lock_utxo(&alice_utxo_100().0, None));
+        });
+    }
+
+    #[test]
+    fn is_account_frozen_follows_freezes() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            assert!(!Utxo::is_account_frozen(&alice));
+
+            assert_ok!(Utxo::freeze_account(Origin::ROOT, alice));
+            assert!(Utxo::is_account_frozen(&alice));
+            assert!(!Utxo::is_account_frozen(&H256::random()));
+
+            assert_ok!(Utxo::unfreeze_account(Origin::ROOT, alice));
+            assert!(!Utxo::is_account_frozen(&alice));
+        });
+    }
+
+    // Input donating one of Alice's UTXOs, signed by her
+    fn donation(parent_output: H256) -> TransactionInput {
+        TransactionInput {
+            parent_output,
+            signature: Signature::from_slice(&alice_pair().sign(&Utxo::donate_payload(&parent_output)).0),
+        }
+    }
+
+    #[test]
+    fn donated_utxo_goes_to_leftover() {
+        with_externalities(&mut new_test_ext(), || {
+            let (hash, _) = alice_utxo_100();
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 3);
+
+            let forged = TransactionInput { parent_output: hash, signature: Signature::from_slice(&ALICE_SIG) };
+            assert_err!(Utxo::donate(Origin::signed(1), forged), "signature must be valid");
+
+            // A spend signature, as seen in the transaction pool, can't donate the UTXO
+            let spend = TransactionInput { parent_output: hash, signature: Signature::from_slice(&ALICE_SIG100) };
+            assert_err!(Utxo::donate(Origin::signed(1), spend), "signature must be valid");
+
+            let input = donation(hash);
+            assert_ok!(Utxo::donate(Origin::signed(1), input.clone()));
+
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 103);
+            assert!(!<UnspentOutputs<Test>>::exists(hash));
+            assert_eq!(utxo_events(), vec![Event::Donated(NATIVE_ASSET, 100)]);
+            assert_err!(Utxo::donate(Origin::signed(1), input), "missing input utxo");
+        });
+    }
+
+    #[test]
+    fn huge_signature_window_does_not_overflow() {
+        with_externalities(&mut new_test_ext(), || {
+            <SignatureWindow<Test>>::put(u64::max_value());
+            let signature = Signature::random();
+
+            run_to_block(1);
+            Utxo::remember_signature(signature);
+            Utxo::expire_recent_signatures(2);
+            assert_eq!(Utxo::recent_signature(signature), Some(1));
+        });
+    }
+
+    #[test]
+    fn replayed_signature_rejected_within_window() {
+        with_externalities(&mut new_test_ext(), || {
+            <SignatureWindow<Test>>::put(10);
+            let (hash, utxo) = alice_utxo_100();
+            let transaction = Transaction {
+                inputs: vec![TransactionInput { parent_output: hash, signature: Signature::from_slice(&ALICE_SIG100) }],
+                outputs: vec![TransactionOutput { value: 100, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() }],
+            };
+
+            run_to_block(1);
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction.clone()));
+
+            // Even if the spent output came back, the same transaction can't be replayed
+            Utxo::insert_utxo(hash, utxo);
+            Utxo::remove_utxo(&Utxo::output_hash(&transaction.outputs[0]));
+            assert_err!(Utxo::execute(Origin::INHERENT, transaction.clone()), "signature recently used");
+
+            Utxo::expire_recent_signatures(10);
+            assert!(Utxo::recent_signature(Signature::from_slice(&ALICE_SIG100)).is_some());
+            Utxo::expire_recent_signatures(11);
+            assert!(Utxo::recent_signature(Signature::from_slice(&ALICE_SIG100)).is_none());
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction));
+        });
+    }
+
+    #[test]
+    fn recent_signatures_cache_is_bounded() {
+        with_externalities(&mut new_test_ext(), || {
+            <MaxRecentSignatures<Test>>::put(2);
+            let signatures: Vec<_> = (0..3).map(|_| H512::random()).collect();
+
+            for signature in signatures.iter() {
+                Utxo::remember_signature(*signature);
+            }
+
+            assert!(Utxo::recent_signature(signatures[0]).is_none());
+            assert!(Utxo::recent_signature(signatures[1]).is_some());
+            assert!(Utxo::recent_signature(signatures[2]).is_some());
+        });
+    }
+
+    #[test]
+    fn bps_part_rounds_down_and_never_overflows() {
+        assert_eq!(Utxo::bps_part(100, 2_000), 20);
+        assert_eq!(Utxo::bps_part(7, 2_000), 1);
+        assert_eq!(Utxo::bps_part(4, 2_000), 0);
+        assert_eq!(Utxo::bps_part(100, 20_000), 100);
+        assert_eq!(Utxo::bps_part(Value::max_value(), 10_000), Value::max_value());
+        assert_eq!(Utxo::bps_part(Value::max_value(), 5_000), Value::max_value() / 2);
+    }
+
+    #[test]
+    fn leftover_split_between_treasury_and_authorities() {
+        with_externalities(&mut new_test_ext(), || {
+            let treasury = H256::random();
+            let authorities = vec![H256::random(), H256::random()];
+            <TreasurySplit<Test>>::put(LeftoverSplit { treasury_bps: 2_000, treasury });
+            let received = |pubkey: &H256| -> Value {
+                Utxo::owned_outputs(pubkey)
+                    .into_iter()
+                    .filter_map(|hash| <UnspentOutputs<Test>>::get(hash))
+                    .map(|utxo| utxo.value)
+                    .sum()
+            };
+
+            // (leftover, treasury part, share of each authority, remainder carried over)
+            let cases = [(100, 20, 40, 0), (7, 1, 3, 0), (3, 0, 1, 1)];
+            for (block, (leftover, to_treasury, to_authority, carried)) in cases.iter().enumerate() {
+                <system::Module<Test>>::set_block_number(block as u64 + 1);
+                <LeftoverTotals<Test>>::insert(NATIVE_ASSET, *leftover);
+                let before = (received(&treasury), received(&authorities[0]), received(&authorities[1]));
+
+                Utxo::spend_leftover(&authorities);
+
+                assert_eq!(received(&treasury) - before.0, *to_treasury);
+                assert_eq!(received(&authorities[0]) - before.1, *to_authority);
+                assert_eq!(received(&authorities[1]) - before.2, *to_authority);
+                assert_eq!(Utxo::leftover_total(NATIVE_ASSET), *carried);
+            }
+        });
+    }
+
+    #[test]
+    fn treasury_share_kept_apart_from_authority_share() {
+        with_externalities(&mut new_test_ext(), || {
+            let authority = H256::random();
+            let gold = 1;
+            <TreasurySplit<Test>>::put(LeftoverSplit { treasury_bps: 5_000, treasury: authority });
+            run_to_block(1);
+
+            // Both shares are worth 50 and would land on the same output
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 100);
+            <LeftoverTotals<Test>>::insert(gold, 100);
+            Utxo::spend_leftover(&[authority]);
+
+            let paid = |asset_id| -> Value {
+                Utxo::owned_outputs(authority)
+                    .into_iter()
+                    .filter_map(|hash| <UnspentOutputs<Test>>::get(hash))
+                    .filter(|utxo| utxo.asset_id == asset_id)
+                    .map(|utxo| utxo.value)
+                    .sum()
+            };
+            assert_eq!(paid(NATIVE_ASSET) + Utxo::claimable_rewards(&authority), 100);
+            assert_eq!(paid(gold), 100);
+            assert_eq!(Utxo::owned_outputs(authority).len(), 3);
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 0);
+            assert_eq!(Utxo::leftover_total(gold), 0);
+            assert_indexes_consistent();
+        });
+    }
+
+    #[test]
+    fn attack_by_swapping_signatures_between_inputs() {
+        with_externalities(&mut new_test_ext(), || {
+            let (alice, bob) = (alice_pair(), bob_pair());
+            let bob_key = H256::from_slice(bob.public().as_ref());
+            assert_ok!(Utxo::mint(Origin::signed(1), 5, bob_key));
+            let bob_utxo = Utxo::owned_outputs(bob_key)[0];
+
+            let mut transaction = spend_jointly(&[(alice_utxo_100().0, &alice), (bob_utxo, &bob)]);
+            let (first, second) = (transaction.inputs[0].signature, transaction.inputs[1].signature);
+            transaction.inputs[0].signature = second;
+            transaction.inputs[1].signature = first;
+
+            assert_eq!(
+                Utxo::first_input_error(&transaction),
+                Some(InputError { index: 0, reason: InputFailure::BadSignature })
+            );
+            assert_err!(Utxo::execute(Origin::INHERENT, transaction), "signature must be valid");
+        });
+    }
+
+    #[test]
+    fn planned_outputs_match_inserted_outputs() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let transaction = signed(Transaction {
+                inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                outputs: vec![
+                    TransactionOutput { value: 30, pubkey: alice, salt: 2, ..Default::default() },
+                    TransactionOutput { value: 70, pubkey: H256::random(), salt: 2, ..Default::default() },
+                ],
+            });
+            let planned = Utxo::planned_outputs(&transaction);
+
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction.clone()));
+            for (hash, output) in planned.iter().zip(transaction.outputs.into_iter()) {
+                assert_eq!(<UnspentOutputs<Test>>::get(hash), Some(output));
+            }
+        });
+    }
+
+    #[test]
+    fn duplicate_output_across_transactions_in_block() {
+        with_externalities(&mut new_test_ext(), || {
+            let output = TransactionOutput { value: 50, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() };
+            let spend = |parent_output| signed(Transaction {
+                inputs: vec![TransactionInput { parent_output, signature: Signature::default() }],
+                outputs: vec![output.clone()],
+            });
+
+            run_to_block(1);
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(alice_utxo().0)));
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, spend(alice_utxo_100().0)),
+                "duplicate output across transactions"
+            );
+
+            run_to_block(2);
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, spend(alice_utxo_100().0)),
+                "output already exists"
+            );
+        });
+    }
+
+    #[test]
+    fn recreating_unspent_output_in_later_block_is_rejected() {
+        with_externalities(&mut new_test_ext(), || {
+            let output = TransactionOutput { value: 50, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() };
+            let hash = Utxo::output_hash(&output);
+            let spend = |parent_output, output: &TransactionOutput| signed(Transaction {
+                inputs: vec![TransactionInput { parent_output, signature: Signature::default() }],
+                outputs: vec![output.clone()],
+            });
+
+            run_to_block(1);
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(alice_utxo().0, &output)));
+
+            // Same key and value, same salt: rejected rather than merged into the existing output
+            run_to_block(3);
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, spend(alice_utxo_100().0, &output)),
+                "output already exists"
+            );
+            assert_eq!(<UnspentOutputs<Test>>::get(&hash), Some(output.clone()));
+            assert_eq!(Utxo::created_at(&hash), 1);
+            assert!(<UnspentOutputs<Test>>::exists(alice_utxo_100().0));
+
+            let resalted = TransactionOutput { salt: 3, ..output };
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(alice_utxo_100().0, &resalted)));
+            assert_indexes_consistent();
+        });
+    }
+
+    #[test]
+    fn utxo_age_histogram_buckets_outputs_by_age() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            for block in &[5, 9] {
+                run_to_block(*block);
+                assert_ok!(Utxo::mint(Origin::signed(1), 5, alice));
+            }
+
+            // Ages at block 10: genesis outputs 10, minted ones 5 and 1
+            assert_eq!(Utxo::utxo_age_histogram(10, &[2, 8]), vec![1, 1, 2]);
+            assert_eq!(Utxo::utxo_age_histogram(10, &[]), vec![4]);
+            assert_eq!(Utxo::utxo_age_histogram(9, &[1]), vec![1, 3]);
+        });
+    }
+
+    #[test]
+    fn faucet_pays_out_of_leftover() {
+        with_externalities(&mut new_test_ext(), || {
+            let requester = H256::random();
+            assert_ok!(Utxo::donate(Origin::signed(1), donation(alice_utxo_100().0)));
+
+            assert_err!(Utxo::faucet(Origin::signed(1), requester, 30), "bad origin: expected to be a root origin");
+            assert_err!(Utxo::faucet(Origin::ROOT, requester, 101), "insufficient dust for faucet");
+            assert_ok!(Utxo::faucet(Origin::ROOT, requester, 30));
+
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 70);
+            let paid = Utxo::owned_outputs(requester);
+            assert_eq!(<UnspentOutputs<Test>>::get(paid[0]).map(|utxo| utxo.value), Some(30));
+        });
+    }
+
+    #[test]
+    fn sweep_consolidates_outputs_of_key() {
+        let alice_utxo_50 = TransactionOutput { value: 50, pubkey: H256::from_slice(&ALICE_KEY), salt: 1, ..Default::default() };
+
+        with_externalities(&mut new_test_ext_with(vec![alice_utxo_100().1, alice_utxo_50]), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let bob = H256::from_slice(bob_pair().public().as_ref());
+            let sweep = Utxo::sweep_transaction(&alice, bob).unwrap();
+            let signature = Signature::from_slice(&alice_pair().sign(&Utxo::signing_payload(&sweep)).0);
+            let signatures = vec![signature; sweep.inputs.len()];
+
+            assert_err!(
+                Utxo::sweep(Origin::signed(1), alice, bob, signatures[..1].to_vec()),
+                "one signature per swept output required"
+            );
+            assert_ok!(Utxo::sweep(Origin::signed(1), alice, bob, signatures));
+
+            assert!(Utxo::owned_outputs(alice).is_empty());
+            let swept = Utxo::owned_outputs(bob);
+            assert_eq!(swept.len(), 1);
+            assert_eq!(<UnspentOutputs<Test>>::get(swept[0]).map(|utxo| utxo.value), Some(150));
+
+            assert_err!(Utxo::sweep(Origin::signed(1), alice, bob, vec![]), "no spendable outputs to sweep");
+        });
+    }
+
+    #[test]
+    fn execute_accepts_inherent_and_signed_origins() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let spend = |parent_output: H256| signed(Transaction {
+                inputs: vec![TransactionInput { parent_output, signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 50, pubkey: alice, salt: parent_output.as_bytes()[0] as u64, ..Default::default() }],
+            });
+            let first = spend(alice_utxo().0);
+            let second = spend(alice_utxo_100().0);
+
+            assert_err!(Utxo::execute(Origin::signed(1), first.clone()), "bad origin: expected to be an inherent origin");
+            assert_err!(Utxo::execute_signed(Origin::INHERENT, second.clone()), "bad origin: expected to be a signed origin");
+
+            assert_ok!(Utxo::execute(Origin::INHERENT, first));
+            assert_ok!(Utxo::execute_signed(Origin::signed(1), second));
+            assert!(!<UnspentOutputs<Test>>::exists(alice_utxo().0));
+            assert!(!<UnspentOutputs<Test>>::exists(alice_utxo_100().0));
+        });
+    }
+
+    #[test]
+    fn value_flow_balances_input_with_output_and_leftover() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let bob = H256::from_slice(bob_pair().public().as_ref());
+            let spend = |outputs| signed(Transaction {
+                inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                outputs,
+            });
+
+            // Payment to Bob with change back to Alice
+            let with_change = spend(vec![
+                TransactionOutput { value: 60, pubkey: bob, salt: 2, ..Default::default() },
+                TransactionOutput { value: 40, pubkey: alice, salt: 2, ..Default::default() },
+            ]);
+            assert_eq!(Utxo::value_flow(&with_change), Ok((100, 100, 0)));
+
+            // Payment leaving a fee behind
+            let with_fee = spend(vec![TransactionOutput { value: 90, pubkey: bob, salt: 2, ..Default::default() }]);
+            assert_eq!(Utxo::value_flow(&with_fee), Ok((100, 90, 10)));
+
+            let missing = Transaction {
+                inputs: vec![TransactionInput { parent_output: H256::random(), signature: Signature::default() }],
+                outputs: with_fee.outputs.clone(),
+            };
+            assert_eq!(Utxo::value_flow(&missing), Err("Invalid transaction inputs"));
+        });
+    }
+
+    #[test]
+    fn leftover_share_clears_stale_lock_at_its_hash() {
+        with_externalities(&mut new_test_ext(), || {
+            let authority = H256::random();
+            <system::Module<Test>>::set_block_number(1);
+            let share = TransactionOutput {
+                value: 10,
+                pubkey: authority,
+                salt: Utxo::leftover_salt(&authority, NATIVE_ASSET, 10),
+                ..Default::default()
+            };
+            let target = Utxo::output_hash(&share);
+            <LockedOutputs<Test>>::insert(target, LockStatus::Locked);
+
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 10);
+            Utxo::spend_leftover(&[authority]);
+
+            assert_eq!(<UnspentOutputs<Test>>::get(target), Some(share));
+            assert!(!<LockedOutputs<Test>>::exists(target));
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 0);
+        });
+    }
+
+    #[test]
+    fn leftover_share_never_overwrites_existing_output() {
+        with_externalities(&mut new_test_ext(), || {
+            let fallback = H256::random();
+            <NoAuthorityPolicy<Test>>::put(LeftoverPolicy::Credit(fallback));
+            run_to_block(1);
+
+            // Somebody creates the output the share would be paid to
+            let (target, share) = Utxo::leftover_share(fallback, NATIVE_ASSET, 10);
+            Utxo::insert_utxo(target, share.clone());
+            Utxo::insert_utxo(target, share);
+            assert_eq!(Utxo::owned_outputs(fallback), vec![target]);
+            assert_eq!(Utxo::utxo_count(), 3);
+
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 10);
+            Utxo::spend_leftover(&[]);
+            assert_eq!(Utxo::owned_outputs(fallback).len(), 2);
+            assert_eq!(Utxo::utxo_count(), 4);
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 0);
+
+            // With every fallback salt taken as well, the share stays in the leftover
+            run_to_block(2);
+            let (target, share) = Utxo::leftover_share(fallback, NATIVE_ASSET, 10);
+            Utxo::insert_utxo(target, share.clone());
+            for attempt in 0..MINT_ATTEMPTS {
+                let taken = TransactionOutput { salt: 2 + attempt, ..share.clone() };
+                Utxo::insert_utxo(Utxo::output_hash(&taken), taken);
+            }
+            let count = Utxo::utxo_count();
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 10);
+            Utxo::spend_leftover(&[]);
+            assert_eq!(Utxo::utxo_count(), count);
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 10);
+            assert_indexes_consistent();
+        });
+    }
+
+    #[test]
+    fn validity_proof_checks_without_state() {
+        let mut ext = new_test_ext();
+        let transaction = with_externalities(&mut ext, || signed(Transaction {
+            inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+            outputs: vec![TransactionOutput { value: 90, pubkey: H256::random(), salt: 2, ..Default::default() }],
+        }));
+
+        let encoded = with_externalities(&mut ext, || {
+            Utxo::validity_proof(&transaction).unwrap().encode()
+        });
+
+        // Checked against a chain which never saw those outputs
+        with_externalities(&mut new_test_ext_with(vec![]), || {
+            let proof = ValidityProof::decode(&mut &encoded[..]).unwrap();
+            assert_eq!((proof.input, proof.output), (100, 90));
+            assert_ok!(Utxo::check_validity_proof(&transaction, &proof));
+
+            let mut inflated = transaction.clone();
+            inflated.outputs[0].value = 101;
+            assert_err!(Utxo::check_validity_proof(&inflated, &proof), "signature must be valid");
+            let inflated = signed(inflated);
+            assert_err!(Utxo::check_validity_proof(&inflated, &proof), "output value must not exceed input value");
+
+            let mut forged = proof.clone();
+            forged.spent[0].0.value = 1_000;
+            assert_err!(Utxo::check_validity_proof(&transaction, &forged), "proof does not match inputs");
+        });
+    }
+
+    #[test]
+    fn output_value_capped_by_max_output_value() {
+        with_externalities(&mut new_test_ext(), || {
+            <MaxOutputValue<Test>>::put(60);
+            let spend = |value| signed(Transaction {
+                inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() }],
+            });
+
+            assert_err!(Utxo::execute(Origin::INHERENT, spend(61)), "output value exceeds maximum");
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(60)));
+        });
+    }
+
+    #[test]
+    fn origin_of_links_outputs_to_creating_transactions() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let first = signed(Transaction {
+                inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 90, pubkey: alice, salt: 2, ..Default::default() }],
+            });
+            let mut second = Transaction {
+                inputs: vec![TransactionInput { parent_output: Utxo::output_hash(&first.outputs[0]), signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 80, pubkey: alice, salt: 3, ..Default::default() }],
+            };
+            sign_inputs(&mut second, &alice_pair());
+
+            assert_ok!(Utxo::execute(Origin::INHERENT, first.clone()));
+            assert_ok!(Utxo::execute(Origin::INHERENT, second.clone()));
+
+            // Walk back from the latest output to genesis
+            let latest = Utxo::output_hash(&second.outputs[0]);
+            assert_eq!(Utxo::origin_of(&latest), Some(Utxo::txid(&second)));
+            let parent = second.inputs[0].parent_output;
+            assert_eq!(Utxo::origin_of(&parent), Some(Utxo::txid(&first)));
+            assert_eq!(Utxo::origin_of(&first.inputs[0].parent_output), None);
+        });
+    }
+
+    #[test]
+    fn exist_many_reports_each_hash() {
+        with_externalities(&mut new_test_ext(), || {
+            let missing = H256::random();
+            assert_eq!(
+                Utxo::exist_many(&[alice_utxo().0, missing, alice_utxo_100().0]),
+                vec![true, false, true]
+            );
+            assert_eq!(Utxo::exist_many(&[]), Vec::<bool>::new());
+        });
+    }
+
+    #[test]
+    fn owner_can_reserve_and_release_output() {
+        with_externalities(&mut new_test_ext(), || {
+            let (hash, _) = alice_utxo_100();
+            let sign = |payload: Vec<u8>| Signature::from_slice(&alice_pair().sign(&payload).0);
+
+            // Only the owner may reserve
+            let stranger_sig = Signature::from_slice(&bob_pair().sign(&Utxo::reserve_payload(&hash)).0);
+            assert_err!(Utxo::reserve(Origin::signed(1), hash, stranger_sig), "signature must be valid");
+            let reserve_sig = sign(Utxo::reserve_payload(&hash));
+            assert_ok!(Utxo::reserve(Origin::signed(1), hash, reserve_sig));
+            assert!(Utxo::reserved_outputs(&hash));
+
+            // A reservation can't be turned into a release
+            assert_err!(Utxo::release(Origin::signed(1), hash, sign(Utxo::reserve_payload(&hash))), "signature must be valid");
+            assert_ok!(Utxo::release(Origin::signed(1), hash, sign(Utxo::release_payload(&hash))));
+            assert!(!Utxo::reserved_outputs(&hash));
+            assert_eq!(utxo_events(), vec![Event::OutputReserved(hash), Event::OutputReleased(hash)]);
+
+            // Nor replayed, even once forgotten by the recent signatures
+            assert_err!(Utxo::reserve(Origin::signed(1), hash, reserve_sig), "signature recently used");
+            <RecentSignatures<Test>>::remove(reserve_sig);
+            assert_err!(Utxo::reserve(Origin::signed(1), hash, reserve_sig), "signature must be valid");
+
+            // Signatures made for another launch of the chain don't count either
+            <LaunchHash<Test>>::put(H256::random());
+            let foreign_sig = sign(Utxo::reserve_payload(&hash));
+            <LaunchHash<Test>>::put(H256::default());
+            assert_err!(Utxo::reserve(Origin::signed(1), hash, foreign_sig), "signature must be valid");
+        });
+    }
+
+    #[test]
+    fn reserved_output_cannot_be_spent_until_released() {
+        with_externalities(&mut new_test_ext(), || {
+            let (hash, _) = alice_utxo_100();
+            let transaction = Transaction {
+                inputs: vec![TransactionInput { parent_output: hash, signature: Signature::from_slice(&ALICE_SIG100) }],
+                outputs: vec![TransactionOutput { value: 100, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() }],
+            };
+            let reserve_sig = Signature::from_slice(&alice_pair().sign(&Utxo::reserve_payload(&hash)).0);
+            assert_ok!(Utxo::reserve(Origin::signed(1), hash, reserve_sig));
+            let release_sig = Signature::from_slice(&alice_pair().sign(&Utxo::release_payload(&hash)).0);
+
+            assert_err!(Utxo::execute(Origin::INHERENT, transaction.clone()), "utxo is reserved");
+
+            // Reservations are only advisory when not enforced
+            <RejectReserved<Test>>::put(false);
+            assert_eq!(Utxo::first_input_error(&transaction), None);
+            <RejectReserved<Test>>::put(true);
+
+            assert_ok!(Utxo::release(Origin::signed(1), hash, release_sig));
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction));
+        });
+    }
+
+    #[test]
+    fn authority_rewards_accumulate_over_blocks() {
+        with_externalities(&mut new_test_ext(), || {
+            let authorities = vec![H256::random(), H256::random()];
+
+            // Even shares, with a remainder carried over to the next block
+            <system::Module<Test>>::set_block_number(1);
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 11);
+            Utxo::spend_leftover(&authorities);
+
+            // Remainder of 1 plus 4 more
+            <system::Module<Test>>::set_block_number(2);
+            <LeftoverTotals<Test>>::mutate(NATIVE_ASSET, |total| *total += 4);
+            Utxo::spend_leftover(&authorities);
+
+            // Too small to share, so it goes to the first authority in turn
+            <system::Module<Test>>::set_block_number(3);
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 1);
+            Utxo::spend_leftover(&authorities);
+
+            // Other assets are not accounted for
+            <LeftoverTotals<Test>>::insert(7, 100);
+            Utxo::spend_leftover(&authorities);
+
+            assert_eq!(Utxo::rewards_of(&authorities[0]), 5 + 2 + 1);
+            assert_eq!(Utxo::rewards_of(&authorities[1]), 5 + 2);
+            assert_eq!(Utxo::rewards_of(&H256::random()), 0);
+        });
+    }
+
+    #[test]
+    fn leftover_untouched_when_redistribution_disabled() {
+        with_externalities(&mut new_test_ext(), || {
+            // Without authorities, redistribution would burn the leftover
+            <NoAuthorityPolicy<Test>>::put(LeftoverPolicy::Burn);
+            <RedistributeLeftover<Test>>::put(false);
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 7);
+
+            Utxo::on_finalize(1);
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 7);
+
+            <RedistributeLeftover<Test>>::put(true);
+            Utxo::on_finalize(1);
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 0);
+        });
+    }
+
+    #[test]
+    fn consolidation_savings_counts_sweepable_outputs() {
+        with_externalities(&mut new_test_ext(), || {
+            let bob = H256::from_slice(bob_pair().public().as_ref());
+            assert_eq!(Utxo::consolidation_savings(&bob), (0, 0));
+
+            for value in 1..=3 {
+                assert_ok!(Utxo::mint(Origin::signed(1), value, bob));
+            }
+            let locked = Utxo::create_output(10, bob).unwrap();
+            assert_ok!(Utxo::lock_utxo(&locked, None));
+
+            assert_eq!(Utxo::consolidation_savings(&bob), (3, 1 + 2 + 3));
+        });
+    }
+
+    #[test]
+    fn multisig_executes_once_every_owner_signed() {
+        with_externalities(&mut new_test_ext(), || {
+            run_to_block(1);
+            let (alice, bob) = (alice_pair(), bob_pair());
+            assert_ok!(Utxo::mint(Origin::signed(1), 5, H256::from_slice(bob.public().as_ref())));
+            let bob_utxo = Utxo::owned_outputs(H256::from_slice(bob.public().as_ref()))[0];
+            let mut transaction = spend_jointly(&[(alice_utxo_100().0, &alice), (bob_utxo, &bob)]);
+            let bob_signature = transaction.inputs[1].signature;
+            transaction.inputs[1].signature = Signature::default();
+            let txid = Utxo::txid(&transaction);
+
+            // Only owners of some input may propose
+            let mut unsigned = transaction.clone();
+            unsigned.inputs[0].signature = Signature::default();
+            assert_err!(Utxo::propose_multisig(Origin::signed(1), unsigned), "signature must be valid");
+            let mut forged = transaction.clone();
+            forged.inputs[0].signature = Signature::random();
+            assert_err!(Utxo::propose_multisig(Origin::signed(1), forged), "signature must be valid");
+            let mut missing = transaction.clone();
+            missing.inputs[1].parent_output = H256::random();
+            assert_err!(Utxo::propose_multisig(Origin::signed(1), missing), "missing input utxo");
+            assert_eq!(Utxo::pending_multisig_count(), 0);
+
+            assert_ok!(Utxo::propose_multisig(Origin::signed(1), transaction.clone()));
+            assert_err!(
+                Utxo::propose_multisig(Origin::signed(2), transaction),
+                "transaction is already pending"
+            );
+            assert_eq!(Utxo::pending_multisig_count(), 1);
+
+            // Bob's signature completes the transaction, which is executed
+            run_to_block(2);
+            assert!(<UnspentOutputs<Test>>::exists(bob_utxo));
+            assert_ok!(Utxo::add_signature(Origin::signed(2), txid, bob_signature));
+            assert!(Utxo::pending_multisig(&txid).is_none());
+            assert_eq!(Utxo::pending_multisig_count(), 0);
+            assert!(!<UnspentOutputs<Test>>::exists(bob_utxo));
+            assert!(!<UnspentOutputs<Test>>::exists(alice_utxo_100().0));
+        });
+    }
+
+    #[test]
+    fn explain_input_reports_keys_signer_and_lock() {
+        with_externalities(&mut new_test_ext(), || {
+            run_to_block(1);
+            let (alice, bob) = (alice_pair(), bob_pair());
+            let bob_key = H256::from_slice(bob.public().as_ref());
+
+            // Simple signature spend
+            let single = alice_spend(alice_utxo_100().0, 90, 2);
+            let explanation = Utxo::explain_input(&single, 0).unwrap();
+            assert_eq!(explanation.condition, SpendCondition::Unconditional);
+            assert_eq!(explanation.allowed_keys, vec![H256::from_slice(&ALICE_KEY)]);
+            assert_eq!(explanation.signer, Some(H256::from_slice(&ALICE_KEY)));
+            assert_eq!(explanation.lock, None);
+            assert!(!explanation.locked);
+            assert_err!(Utxo::explain_input(&single, 1), "no such input");
+
+            // Multisig spend, with Bob's input still unsigned and time-locked
+            assert_ok!(Utxo::mint(Origin::signed(1), 5, bob_key));
+            let bob_utxo = Utxo::owned_outputs(bob_key)[0];
+            assert_ok!(Utxo::lock_utxo(&bob_utxo, Some(3)));
+            let mut joint = spend_jointly(&[(alice_utxo_100().0, &alice), (bob_utxo, &bob)]);
+            let bob_index = joint.inputs.iter().position(|input| input.parent_output == bob_utxo).unwrap();
+            assert_eq!(Utxo::explain_input(&joint, 1 - bob_index).unwrap().signer, Some(H256::from_slice(&ALICE_KEY)));
+
+            joint.inputs[bob_index].signature = Signature::default();
+            let explanation = Utxo::explain_input(&joint, bob_index).unwrap();
+            assert_eq!(explanation.allowed_keys, vec![bob_key]);
+            assert_eq!(explanation.signer, None);
+            assert_eq!(explanation.lock, Some(LockStatus::LockedUntil(3)));
+            assert!(explanation.locked);
+        });
+    }
+
+    // A transaction spending a new UTXO of Bob along with `parent_output` of Alice,
+    // signed by Alice only
+    fn signed_by_alice_for_bob(parent_output: H256, value: Value) -> Transaction {
+        let bob = H256::from_slice(bob_pair().public().as_ref());
+        let bob_utxo = Utxo::create_output(value, bob).unwrap();
+        let mut transaction = spend_jointly(&[(parent_output, &alice_pair()), (bob_utxo, &bob_pair())]);
+        transaction.inputs[1].signature = Signature::default();
+        transaction
+    }
+
+    #[test]
+    fn pending_multisig_expires() {
+        with_externalities(&mut new_test_ext(), || {
+            <MultisigLifetime<Test>>::put(2);
+            run_to_block(1);
+            let transaction = signed_by_alice_for_bob(alice_utxo_100().0, 5);
+            let txid = Utxo::txid(&transaction);
+            assert_ok!(Utxo::propose_multisig(Origin::signed(1), transaction));
+
+            run_to_block(2);
+            assert!(Utxo::pending_multisig(&txid).is_some());
+            run_to_block(3);
+            assert!(Utxo::pending_multisig(&txid).is_none());
+            assert_eq!(Utxo::pending_multisig_count(), 0);
+            assert_err!(
+                Utxo::add_signature(Origin::signed(1), txid, Signature::from_slice(&ALICE_SIG100)),
+                "no such pending transaction"
+            );
+        });
+    }
+
+    #[test]
+    fn pending_multisig_capped() {
+        with_externalities(&mut new_test_ext(), || {
+            <MaxPendingMultisig<Test>>::put(1);
+            <MultisigLifetime<Test>>::put(2);
+            run_to_block(1);
+            assert_ok!(Utxo::propose_multisig(Origin::signed(1), signed_by_alice_for_bob(alice_utxo_100().0, 5)));
+            assert_err!(
+                Utxo::propose_multisig(Origin::signed(1), signed_by_alice_for_bob(alice_utxo().0, 6)),
+                "too many pending transactions"
+            );
+
+            // Fully signed transactions don't wait, so they are never capped
+            let transaction = spend_jointly(&[(alice_utxo().0, &alice_pair())]);
+            assert_ok!(Utxo::propose_multisig(Origin::signed(1), transaction));
+            assert!(!<UnspentOutputs<Test>>::exists(alice_utxo().0));
+
+            // Room is made as pending transactions expire
+            run_to_block(3);
+            assert_eq!(Utxo::pending_multisig_count(), 0);
+            let transaction = signed_by_alice_for_bob(alice_utxo_100().0, 7);
+            assert_ok!(Utxo::propose_multisig(Origin::signed(1), transaction));
+            assert_eq!(Utxo::pending_multisig_count(), 1);
+        });
+    }
+
+    #[test]
+    fn signatures_are_bound_to_chain_launch() {
+        with_externalities(&mut new_test_ext(), || {
+            let mut transaction = Transaction {
+                inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 100, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() }],
+            };
+
+            // Signed for the same UTXO set, but on another launch of the chain
+            <LaunchHash<Test>>::put(H256::random());
+            sign_inputs(&mut transaction, &alice_pair());
+            <LaunchHash<Test>>::put(H256::default());
+
+            assert_err!(Utxo::execute(Origin::INHERENT, transaction.clone()), "signature must be valid");
+
+            sign_inputs(&mut transaction, &alice_pair());
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction));
+        });
+    }
+
+    #[test]
+    fn rejected_transactions_emit_event_when_enabled() {
+        with_externalities(&mut new_test_ext(), || {
+            let transaction = Transaction {
+                inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::from_slice(&ALICE_SIG) }],
+                outputs: vec![TransactionOutput { value: 100, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() }],
+            };
+
+            assert_err!(Utxo::execute(Origin::INHERENT, transaction.clone()), "signature must be valid");
+            assert_eq!(utxo_events(), vec![]);
+
+            <EmitRejections<Test>>::put(true);
+            assert_err!(Utxo::execute(Origin::INHERENT, transaction.clone()), "signature must be valid");
+            assert_eq!(
+                utxo_events(),
+                vec![Event::TransactionRejected(Utxo::txid(&transaction), 12, b"signature must be valid".to_vec())]
+            );
+        });
+    }
+
+    #[test]
+    fn leftover_overflow_of_one_asset_leaves_others_untouched() {
+        with_externalities(&mut new_test_ext(), || {
+            <BurnFraction<Test>>::put(2_500);
+            let alice = H256::from_slice(&ALICE_KEY);
+            let gold = |value, salt| TransactionOutput { value, pubkey: alice, salt, asset_id: 1, ..Default::default() };
+            let gold_utxo = Utxo::output_hash(&gold(50, 0));
+            Utxo::insert_utxo(gold_utxo, gold(50, 0));
+            <LeftoverTotals<Test>>::insert(1, Value::max_value());
+
+            let mut transaction = Transaction {
+                inputs: vec![
+                    TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() },
+                    TransactionInput { parent_output: gold_utxo, signature: Signature::default() },
+                ],
+                outputs: vec![
+                    TransactionOutput { value: 90, pubkey: alice, salt: 1, ..Default::default() },
+                    gold(30, 1),
+                ],
+            };
+            sign_inputs(&mut transaction, &alice_pair());
+
+            // The native leftover comes first, but is only written if every asset fits
+            assert_err!(Utxo::execute(Origin::INHERENT, transaction), "Leftover overflow");
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 0);
+            assert_eq!(Utxo::leftover_total(1), Value::max_value());
+            assert!(utxo_events().is_empty());
+            assert!(<UnspentOutputs<Test>>::exists(alice_utxo_100().0));
+        });
+    }
+
+    #[test]
+    fn burn_fraction_of_leftover_is_destroyed() {
+        with_externalities(&mut new_test_ext(), || {
+            <BurnFraction<Test>>::put(2_500);
+            let transaction = signed(Transaction {
+                inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 90, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() }],
+            });
+
+            // A quarter of the leftover of 10, rounded down
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction));
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 8);
+            assert!(utxo_events().contains(&Event::LeftoverBurnt(NATIVE_ASSET, 2)));
+        });
+    }
+
+    #[test]
+    fn dust_distributability_boundaries() {
+        with_externalities(&mut new_test_ext(), || {
+            assert!(!Utxo::dust_is_distributable(0, 3));
+            assert!(Utxo::dust_is_distributable(1, 3));
+            assert!(Utxo::dust_is_distributable(3, 3));
+
+            // Without authorities, only a fallback key gets paid
+            assert!(!Utxo::dust_is_distributable(3, 0));
+            <NoAuthorityPolicy<Test>>::put(LeftoverPolicy::Credit(H256::random()));
+            assert!(Utxo::dust_is_distributable(3, 0));
+
+            // Burning rounds down, so a single unit is never burnt short of 100%
+            <BurnFraction<Test>>::put(9_999);
+            assert!(Utxo::dust_is_distributable(1, 3));
+            assert!(Utxo::dust_is_distributable(10_000, 3));
+            <BurnFraction<Test>>::put(10_000);
+            assert!(!Utxo::dust_is_distributable(1, 3));
+        });
+    }
+
+    #[test]
+    fn outputs_created_in_each_block_are_listed() {
+        with_externalities(&mut new_test_ext(), || {
+            <CreationHistory<Test>>::put(3);
+            let alice = H256::from_slice(&ALICE_KEY);
+            let first = signed(Transaction {
+                inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                outputs: vec![
+                    TransactionOutput { value: 40, pubkey: alice, salt: 2, ..Default::default() },
+                    TransactionOutput { value: 60, pubkey: alice, salt: 2, ..Default::default() },
+                ],
+            });
+
+            run_to_block(1);
+            assert_ok!(Utxo::execute(Origin::INHERENT, first.clone()));
+            run_to_block(2);
+            let minted = Utxo::create_output(5, alice).unwrap();
+
+            assert_eq!(Utxo::outputs_created_in(1), Utxo::planned_outputs(&first));
+            assert_eq!(Utxo::outputs_created_in(2), vec![minted]);
+            assert!(Utxo::outputs_created_in(3).is_empty());
+
+            // Dropped once out of the history window
+            run_to_block(4);
+            assert!(Utxo::outputs_created_in(1).is_empty());
+            assert_eq!(Utxo::outputs_created_in(2), vec![minted]);
+        });
+    }
+
+    #[test]
+    fn strict_balance_rejects_any_leftover() {
+        with_externalities(&mut new_test_ext(), || {
+            <StrictBalance<Test>>::put(true);
+            let spend = |value| signed(Transaction {
+                inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() }],
+            });
+
+            assert_err!(Utxo::execute(Origin::INHERENT, spend(99)), "dust not permitted in strict mode");
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(100)));
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 0);
+        });
+    }
+
+    #[test]
+    fn min_fee_required_in_native_leftover() {
+        with_externalities(&mut new_test_ext(), || {
+            <MinFee<Test>>::put(10);
+
+            assert_err!(Utxo::check_transaction(&alice_spend(alice_utxo_100().0, 91, 2)), "fee below minimum");
+            assert_ok!(Utxo::execute(Origin::INHERENT, alice_spend(alice_utxo_100().0, 90, 2)));
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 10);
+        });
+    }
+
+    // Builds a transaction spending the given UTXOs of Alice in one batch
+    fn spend_batched(parents: &[H256]) -> (Transaction, BatchProof) {
+        let transaction = Transaction {
+            inputs: parents
+                .iter()
+                .map(|parent_output| TransactionInput { parent_output: *parent_output, signature: Signature::default() })
+                .collect(),
+            outputs: vec![TransactionOutput { value: 100, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() }],
+        };
+        let root = Utxo::batch_root(parents);
+        let proof = BatchProof {
+            signer: H256::from_slice(&ALICE_KEY),
+            root,
+            signature: Signature::from_slice(&alice_pair().sign(&Utxo::batch_payload(&transaction, &root)).0),
+            paths: (0..parents.len()).map(|index| Utxo::batch_path(parents, index)).collect(),
+        };
+        (transaction, proof)
+    }
+
+    #[test]
+    fn batched_inputs_spent_with_single_signature() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let minted: Vec<_> = (1..=3).map(|value| Utxo::create_output(value, alice).unwrap()).collect();
+            let parents = vec![alice_utxo_100().0, minted[0], minted[1], minted[2]];
+            let (transaction, proof) = spend_batched(&parents);
+
+            assert_ok!(Utxo::execute_batched(Origin::signed(1), transaction, proof));
+            assert!(Utxo::exist_many(&parents).iter().all(|exists| !exists));
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 6);
+            assert_indexes_consistent();
+        });
+    }
+
+    #[test]
+    fn tampered_batch_proofs_are_rejected() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let minted = Utxo::create_output(1, alice).unwrap();
+            let (transaction, proof) = spend_batched(&[alice_utxo_100().0, minted]);
+
+            let mut tampered = proof.clone();
+            tampered.paths[1] = vec![H256::random()];
+            assert_err!(
+                Utxo::execute_batched(Origin::signed(1), transaction.clone(), tampered),
+                "input not included in batch"
+            );
+
+            // Signed for another root
+            let mut tampered = proof.clone();
+            tampered.root = Utxo::batch_root(&[alice_utxo_100().0]);
+            tampered.paths = vec![vec![], vec![]];
+            assert_err!(
+                Utxo::execute_batched(Origin::signed(1), transaction.clone(), tampered),
+                "signature must be valid"
+            );
+
+            // Valid proof by a key not allowed to spend the inputs
+            let bob = H256::from_slice(bob_pair().public().as_ref());
+            let mut foreign = proof.clone();
+            foreign.signer = bob;
+            foreign.signature = Signature::from_slice(&bob_pair().sign(&Utxo::batch_payload(&transaction, &proof.root)).0);
+            assert_err!(
+                Utxo::execute_batched(Origin::signed(1), transaction.clone(), foreign),
+                "signature must be valid"
+            );
+
+            // The proof authorizes this transaction only, not others spending the same inputs
+            let mut redirected = transaction.clone();
+            redirected.outputs[0].pubkey = bob;
+            assert_err!(
+                Utxo::execute_batched(Origin::signed(1), redirected, proof.clone()),
+                "signature must be valid"
+            );
+
+            assert_ok!(Utxo::execute_batched(Origin::signed(1), transaction, proof));
+        });
+    }
+
+    #[test]
+    fn batched_transactions_validated_for_pool_by_proof() {
+        with_externalities(&mut new_test_ext(), || {
+            let now = <system::Module<Test>>::block_number();
+            let alice = H256::from_slice(&ALICE_KEY);
+            let minted = Utxo::create_output(1, alice).unwrap();
+            let (transaction, proof) = spend_batched(&[alice_utxo_100().0, minted]);
+
+            // Input signatures are ignored in favor of the proof
+            assert_err!(Utxo::validate_for_pool(&transaction, now), "signature must be valid");
+            let valid = Utxo::validate_for_pool_by(&transaction, Some(&proof), now).unwrap();
+            assert_eq!(valid.longevity, POOL_LONGEVITY);
+
+            let mut tampered = proof.clone();
+            tampered.paths[1] = vec![H256::random()];
+            assert_err!(
+                Utxo::validate_for_pool_by(&transaction, Some(&tampered), now),
+                "input not included in batch"
+            );
+        });
+    }
+
+    #[test]
+    fn value_extremes_of_spendable_outputs() {
+        with_externalities(&mut new_test_ext(), || {
+            let bob = H256::from_slice(bob_pair().public().as_ref());
+            assert_eq!(Utxo::value_extremes(&bob), None);
+
+            let first = Utxo::create_output(30, bob).unwrap();
+            assert_eq!(Utxo::value_extremes(&bob), Some((30, 30)));
+
+            for value in &[10, 50] {
+                Utxo::create_output(*value, bob).unwrap();
+            }
+            // Locked outputs can't be spent now
+            let locked = Utxo::create_output(70, bob).unwrap();
+            assert_ok!(Utxo::lock_utxo(&locked, None));
+            assert_eq!(Utxo::value_extremes(&bob), Some((10, 50)));
+
+            assert_ok!(Utxo::lock_utxo(&first, None));
+            assert_eq!(Utxo::value_extremes(&bob), Some((10, 50)));
+        });
+    }
+
+    #[test]
+    fn new_outputs_lockable_after_grace_period() {
+        with_externalities(&mut new_test_ext(), || {
+            <LockGracePeriod<Test>>::put(3);
+            run_to_block(2);
+            let hash = Utxo::create_output(5, H256::from_slice(&ALICE_KEY)).unwrap();
+
+            assert_err!(Utxo::lock_utxo(&hash, None), "utxo too new to lock");
+            run_to_block(4);
+            assert_err!(Utxo::lock_utxo(&hash, None), "utxo too new to lock");
+            run_to_block(5);
+            assert_ok!(Utxo::lock_utxo(&hash, None));
+
+            // Genesis outputs are old enough already
+            assert_ok!(Utxo::lock_utxo(&alice_utxo_100().0, None));
+        });
+    }
+
+    #[test]
+    fn lock_status_round_trips_through_json() {
+        for status in vec![LockStatus::Locked, LockStatus::LockedUntil(42u64)] {
+            let json = serde_json::to_string(&status).unwrap();
+            assert_eq!(serde_json::from_str::<LockStatus<u64>>(&json).unwrap(), status);
+        }
+
+        assert_eq!(serde_json::to_string(&LockStatus::<u64>::Locked).unwrap(), r#""Locked""#);
+        assert_eq!(serde_json::to_string(&LockStatus::LockedUntil(42u64)).unwrap(), r#"{"LockedUntil":42}"#);
+    }
+
+    #[test]
+    fn select_exact_finds_fewest_outputs_adding_up() {
+        with_externalities(&mut new_test_ext(), || {
+            let bob = H256::from_slice(bob_pair().public().as_ref());
+            let hashes: Vec<_> = [3, 5, 7, 11, 13, 17].iter().map(|value| Utxo::create_output(*value, bob).unwrap()).collect();
+            let values = |selected: Option<Vec<(H256, TransactionOutput)>>| {
+                selected.map(|outputs| outputs.into_iter().map(|(_, output)| output.value).collect::<Vec<_>>())
+            };
+
+            assert_eq!(values(Utxo::select_exact(&bob, 7)), Some(vec![7]));
+            assert_eq!(values(Utxo::select_exact(&bob, 18)), Some(vec![5, 13]));
+            assert_eq!(values(Utxo::select_exact(&bob, 15)), Some(vec![3, 5, 7]));
+            assert_eq!(Utxo::select_exact(&bob, 13).unwrap()[0].0, hashes[4]);
+
+            // Unreachable sums, or only with more inputs than allowed
+            assert_eq!(Utxo::select_exact(&bob, 1), None);
+            assert_eq!(Utxo::select_exact(&bob, 39), None);
+            assert_eq!(Utxo::select_exact(&bob, 57), None);
+            assert_eq!(Utxo::select_exact(&bob, 0), None);
+            assert_eq!(Utxo::select_exact(&H256::random(), 7), None);
+        });
+    }
+
+    #[test]
+    fn leftover_conserved_across_distributions() {
+        with_externalities(&mut new_test_ext(), || {
+            let treasury = H256::random();
+            let authorities = vec![H256::random(), H256::random(), H256::random()];
+            <TreasurySplit<Test>>::put(LeftoverSplit { treasury_bps: 1_500, treasury });
+            let received = || -> Value {
+                authorities
+                    .iter()
+                    .chain(Some(&treasury))
+                    .flat_map(|pubkey| Utxo::owned_outputs(pubkey))
+                    .filter_map(|hash| <UnspentOutputs<Test>>::get(hash))
+                    .map(|utxo| utxo.value)
+                    .sum()
+            };
+
+            // Even shares with remainders, and too little to share
+            let mut collected = 0;
+            for (block, leftover) in [101, 10, 2, 1_000_003].iter().enumerate() {
+                <system::Module<Test>>::set_block_number(block as u64 + 1);
+                <LeftoverTotals<Test>>::mutate(NATIVE_ASSET, |total| *total += *leftover);
+                collected += *leftover;
+
+                // Also checked by the debug assertion in `spend_asset_leftover`
+                Utxo::spend_leftover(&authorities);
+                assert_eq!(received() + Utxo::leftover_total(NATIVE_ASSET), collected);
+            }
+        });
+    }
+
+    #[test]
+    fn demurrage_output_loses_value_with_age() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let utxo = TransactionOutput {
+                value: 100,
+                pubkey: alice,
+                salt: 0,
+                condition: SpendCondition::Demurrage { rate_bps: 1_000, period: 10 },
+                asset_id: NATIVE_ASSET,
+                expires_at: None,
+            };
+            let hash = BlakeTwo256::hash_of(&utxo);
+            run_to_block(1);
+            Utxo::insert_utxo(hash, utxo);
+
+            let spend = |inputs: &[H256], value| {
+                let mut transaction = Transaction {
+                    inputs: inputs.iter().map(|parent_output| TransactionInput { parent_output: *parent_output, signature: Signature::default() }).collect(),
+                    outputs: vec![TransactionOutput { value, pubkey: alice, salt: 2, ..Default::default() }],
+                };
+                sign_inputs(&mut transaction, &alice_pair());
+                transaction
+            };
+
+            // Not a full period yet
+            run_to_block(10);
+            assert_eq!(Utxo::value_flow(&spend(&[hash], 100)), Ok((100, 100, 0)));
+
+            // Two full periods: 20% decayed, which counts as leftover
+            run_to_block(21);
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, spend(&[hash], 81)),
+                "output value must not exceed input value"
+            );
+            assert_eq!(Utxo::value_flow(&spend(&[hash], 80)), Ok((100, 80, 20)));
+
+            // Fully decayed: worth nothing, though still spendable
+            run_to_block(200);
+            assert_err!(
+                Utxo::execute(Origin::INHERENT, spend(&[hash, alice_utxo_100().0], 101)),
+                "output value must not exceed input value"
+            );
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(&[hash, alice_utxo_100().0], 100)));
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 100);
+        });
+    }
+
+    #[test]
+    fn reexecution_writes_identical_storage() {
+        let authorities = vec![H256::random(), H256::random()];
+        let apply = || {
+            with_externalities(&mut new_test_ext(), || {
+                run_to_block(1);
+                let transaction = signed(Transaction {
+                    inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                    outputs: vec![TransactionOutput { value: 91, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() }],
+                });
+                assert_ok!(Utxo::execute(Origin::INHERENT, transaction));
+                Utxo::spend_leftover(&authorities);
+                runtime_io::storage_root()
+            })
+        };
+
+        // Starting over from the same state, as after a reorg
+        assert_eq!(apply(), apply());
+    }
+
+    #[test]
+    fn verify_transaction_signatures_reports_invalid_input() {
+        with_externalities(&mut new_test_ext(), || {
+            let mut transaction = Transaction {
+                inputs: vec![
+                    TransactionInput { parent_output: alice_utxo().0, signature: Signature::default() },
+                    TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() },
+                ],
+                outputs: vec![TransactionOutput { value: 100, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() }],
+            };
+            sign_inputs(&mut transaction, &alice_pair());
+            assert_eq!(Utxo::verify_transaction_signatures(&transaction), Ok(()));
+
+            // Valid signature of the transaction, but by a key not owning the input
+            transaction.inputs[1].signature = Signature::from_slice(&bob_pair().sign(&Utxo::signing_payload(&transaction)).0);
+            assert_eq!(
+                Utxo::verify_transaction_signatures(&transaction),
+                Err(InputError { index: 1, reason: InputFailure::BadSignature })
+            );
+
+            // Locks don't matter here
+            sign_inputs(&mut transaction, &alice_pair());
+            assert_ok!(Utxo::lock_utxo(&alice_utxo().0, None));
+            assert_eq!(Utxo::verify_transaction_signatures(&transaction), Ok(()));
+        });
+    }
+
+    #[test]
+    fn tiny_leftover_rotates_by_key_whatever_the_reported_order() {
+        with_externalities(&mut new_test_ext(), || {
+            <LeftoverOrder<Test>>::put(AuthorityOrder::ByKey);
+            let mut sorted = vec![H256::random(), H256::random(), H256::random()];
+            sorted.sort();
+
+            // Consensus reports authorities in a different order every block
+            let reported = [[2, 0, 1], [1, 2, 0], [0, 2, 1], [2, 1, 0]];
+            for (block, order) in reported.iter().enumerate() {
+                <system::Module<Test>>::set_block_number(block as u64 + 1);
+                <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 1);
+
+                let authorities: Vec<_> = order.iter().map(|index| sorted[*index]).collect();
+                Utxo::spend_leftover(&authorities);
+
+                let expected = sorted[block % sorted.len()];
+                assert_eq!(Utxo::rewards_of(&expected), if block < sorted.len() { 1 } else { 2 });
+            }
+        });
+    }
+
+    #[test]
+    fn block_dust_sums_current_block_transactions() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let spend = |parent_output, value| signed(Transaction {
+                inputs: vec![TransactionInput { parent_output, signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value, pubkey: alice, salt: 2, ..Default::default() }],
+            });
+
+            run_to_block(1);
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(alice_utxo_100().0, 93)));
+            assert_ok!(Utxo::execute(Origin::INHERENT, spend(alice_utxo().0, Value::max_value() - 5)));
+            assert_eq!(Utxo::block_dust(), 7 + 5);
+
+            run_to_block(2);
+            assert_eq!(Utxo::block_dust(), 0);
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 7 + 5);
+
+            // Leftover of rejected transactions is not counted
+            let change = Utxo::output_hash(&TransactionOutput { value: 93, pubkey: alice, salt: 2, ..Default::default() });
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, Value::max_value());
+            assert_err!(Utxo::execute(Origin::INHERENT, spend(change, 90)), "Leftover overflow");
+            assert_eq!(Utxo::block_dust(), 0);
+        });
+    }
+
+    #[test]
+    fn input_referring_to_own_output_rejected() {
+        with_externalities(&mut new_test_ext(), || {
+            let output = TransactionOutput { value: 50, pubkey: H256::from_slice(&ALICE_KEY), salt: 2, ..Default::default() };
+            let mut transaction = Transaction {
+                inputs: vec![
+                    TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() },
+                    TransactionInput { parent_output: Utxo::output_hash(&output), signature: Signature::default() },
+                ],
+                outputs: vec![output],
+            };
+            sign_inputs(&mut transaction, &alice_pair());
+
+            assert_err!(Utxo::execute(Origin::INHERENT, transaction.clone()), "input references own output");
+            assert_err!(Utxo::pool_priority(&transaction), "input references own output");
+        });
+    }
+
+    // Builds a transaction from Alice, paying `value` back to her
+    fn alice_spend(parent_output: H256, value: Value, salt: u64) -> Transaction {
+        let mut transaction = Transaction {
+            inputs: vec![TransactionInput { parent_output, signature: Signature::default() }],
+            outputs: vec![TransactionOutput { value, pubkey: H256::from_slice(&ALICE_KEY), salt, ..Default::default() }],
+        };
+        sign_inputs(&mut transaction, &alice_pair());
+        transaction
+    }
+
+    #[test]
+    fn validate_for_pool_admits_ready_and_pending_transactions() {
+        with_externalities(&mut new_test_ext(), || {
+            let now = <system::Module<Test>>::block_number();
+
+            let transaction = alice_spend(alice_utxo_100().0, 90, 2);
+            let valid = Utxo::validate_for_pool(&transaction, now).unwrap();
+            assert_eq!(valid.priority, Utxo::pool_priority(&transaction).unwrap());
+            assert!(valid.priority > 0);
+            assert!(valid.requires.is_empty());
+            assert_eq!(valid.provides, Utxo::pool_tags(&transaction).1);
+            assert_eq!(valid.longevity, POOL_LONGEVITY);
+
+            // A child of the pending transaction waits for its parent's output
+            let parent = Utxo::output_hash(&transaction.outputs[0]);
+            let child = alice_spend(parent, 90, 3);
+            let pending = Utxo::validate_for_pool(&child, now).unwrap();
+            assert_eq!(pending.priority, 0);
+            assert_eq!(pending.requires, vec![parent.as_fixed_bytes().to_vec()]);
+            assert_eq!(
+                TransactionValidity::from(pending.clone()),
+                TransactionValidity::Valid {
+                    priority: 0,
+                    requires: pending.requires,
+                    provides: pending.provides,
+                    longevity: POOL_LONGEVITY,
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn validate_for_pool_rejects_inadmissible_transactions() {
+        with_externalities(&mut new_test_ext(), || {
+            let now = <system::Module<Test>>::block_number();
+            let alice = H256::from_slice(&ALICE_KEY);
+            let (hash, _) = alice_utxo_100();
+            let transaction = alice_spend(hash, 90, 2);
+
+            let mut forged = transaction.clone();
+            forged.inputs[0].signature = Signature::random();
+            assert_err!(Utxo::validate_for_pool(&forged, now), "signature must be valid");
+
+            let overspending = alice_spend(hash, 101, 2);
+            assert_err!(Utxo::validate_for_pool(&overspending, now), "output value must not exceed input value");
+
+            <MaxOutputValue<Test>>::put(50);
+            assert_err!(Utxo::validate_for_pool(&transaction, now), "output value exceeds maximum");
+            <MaxOutputValue<Test>>::put(Value::max_value());
+
+            assert_ok!(Utxo::lock_utxo(&hash, None));
+            assert_err!(Utxo::validate_for_pool(&transaction, now), "utxo is locked");
+            <LockedOutputs<Test>>::remove(&hash);
+
+            <FrozenAccounts<Test>>::insert(&alice, true);
+            assert_err!(Utxo::validate_for_pool(&transaction, now), "account is frozen");
+            <FrozenAccounts<Test>>::remove(&alice);
+
+            assert!(Utxo::validate_for_pool(&transaction, now).is_ok());
+
+            let mut too_many_inputs = transaction.clone();
+            too_many_inputs.inputs = vec![transaction.inputs[0].clone(); MAX_POOL_TX_INPUTS + 1];
+            assert_err!(Utxo::validate_for_pool(&too_many_inputs, now), "too many inputs");
+
+            let mut too_large = transaction.clone();
+            too_large.outputs = (0..MAX_POOL_TX_SIZE as u64 / 32)
+                .map(|salt| TransactionOutput { value: 1, pubkey: alice, salt, ..Default::default() })
+                .collect();
+            sign_inputs(&mut too_large, &alice_pair());
+            assert_err!(Utxo::validate_for_pool(&too_large, now), "transaction too large");
+
+            // Spend conditions are evaluated at the given block
+            let escrow = refundable_utxo();
+            let refund = claim_refundable(escrow, &alice_pair());
+            assert_err!(Utxo::validate_for_pool(&refund, 9), "signature must be valid");
+            assert!(Utxo::validate_for_pool(&refund, 10).is_ok());
+        });
+    }
+
+    #[test]
+    fn utxo_set_large_deposited_once_per_crossing() {
+        with_externalities(&mut new_test_ext(), || {
+            let warnings = || utxo_events().into_iter().filter(|event| match event {
+                Event::UtxoSetLarge(_) => true,
+                _ => false,
+            }).count();
+            let output = |salt| TransactionOutput { value: 1, pubkey: H256::random(), salt, ..Default::default() };
+
+            <UtxoSetWarnThreshold<Test>>::put(3);
+            assert_eq!(Utxo::utxo_count(), 2);
+            Utxo::insert_utxo(H256::random(), output(1));
+            Utxo::on_finalize(1);
+            assert_eq!(warnings(), 0);
+
+            Utxo::insert_utxo(H256::random(), output(2));
+            for block in 1..4 {
+                Utxo::on_finalize(block);
+            }
+            assert_eq!(warnings(), 1);
+            assert!(utxo_events().contains(&Event::UtxoSetLarge(4)));
+
+            // Warns again once the set has shrunk and grown back
+            assert!(Utxo::remove_utxo(&alice_utxo_100().0).is_some());
+            Utxo::on_finalize(4);
+            Utxo::insert_utxo(H256::random(), output(3));
+            Utxo::on_finalize(5);
+            assert_eq!(warnings(), 2);
+            assert_eq!(Utxo::utxo_count(), Utxo::stored_utxo_count());
+
+            // Chains from before the counter count their UTXO set on upgrade
+            <UtxoCount<Test>>::kill();
+            <StorageVersion<Test>>::put(2);
+            Utxo::on_runtime_upgrade();
+            assert_eq!(Utxo::utxo_count(), 4);
+        });
+    }
+
+    #[test]
+    fn accrued_rewards_claimed_as_one_output() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let authorities = vec![alice, H256::random()];
+            <AccrueRewards<Test>>::put(true);
+
+            for (block, leftover) in [(1, 10), (2, 6)].iter() {
+                <system::Module<Test>>::set_block_number(*block);
+                <LeftoverTotals<Test>>::insert(NATIVE_ASSET, *leftover);
+                Utxo::spend_leftover(&authorities);
+            }
+            assert_eq!(Utxo::claimable_rewards(&alice), 5 + 3);
+            assert_eq!(Utxo::rewards_of(&alice), 5 + 3);
+            assert_eq!(Utxo::utxo_count(), 2);
+
+            let signature = Signature::from_slice(&alice_pair().sign(&Utxo::claim_payload(&alice)).0);
+            assert_err!(
+                Utxo::claim_rewards(Origin::signed(1), authorities[1], signature),
+                "signature must be valid"
+            );
+            assert_ok!(Utxo::claim_rewards(Origin::signed(1), alice, signature));
+
+            let claimed = Utxo::owned_outputs(alice)
+                .into_iter()
+                .filter_map(|hash| <UnspentOutputs<Test>>::get(hash))
+                .find(|utxo| utxo.value == 8);
+            assert!(claimed.is_some());
+            assert_eq!(Utxo::claimable_rewards(&alice), 0);
+            assert_err!(Utxo::claim_rewards(Origin::signed(1), alice, signature), "no rewards to claim");
+        });
+    }
+
+    #[test]
+    fn colliding_leftover_share_kept_for_claim() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+
+            // The same share twice in a block maps to the same output
+            for _ in 0..2 {
+                <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 4);
+                Utxo::spend_leftover(&[alice]);
+            }
+
+            assert_eq!(Utxo::utxo_count(), 3);
+            assert_eq!(Utxo::claimable_rewards(&alice), 4);
+            assert_eq!(Utxo::rewards_of(&alice), 8);
+        });
+    }
+
+    #[test]
+    fn block_value_summary_sums_transactions() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let bob = H256::from_slice(bob_pair().public().as_ref());
+            assert_ok!(Utxo::mint(Origin::signed(1), 50, bob));
+            let minted = TransactionOutput { value: 50, pubkey: bob, salt: 0, ..Default::default() };
+
+            // Alice pays Bob with change and a fee, Bob pays Alice without a fee
+            let mut payment = Transaction {
+                inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                outputs: vec![
+                    TransactionOutput { value: 60, pubkey: bob, salt: 2, ..Default::default() },
+                    TransactionOutput { value: 35, pubkey: alice, salt: 2, ..Default::default() },
+                ],
+            };
+            sign_inputs(&mut payment, &alice_pair());
+            let mut refund = Transaction {
+                inputs: vec![TransactionInput { parent_output: Utxo::output_hash(&minted), signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 50, pubkey: alice, salt: 3, ..Default::default() }],
+            };
+            sign_inputs(&mut refund, &bob_pair());
+
+            assert_eq!(Utxo::block_value_summary(&[]), Ok((0, 0, 0)));
+            assert_eq!(Utxo::block_value_summary(&[payment.clone(), refund.clone()]), Ok((150, 145, 5)));
+
+            let mut double_spend = payment.clone();
+            double_spend.outputs[1].value = 30;
+            assert_eq!(
+                Utxo::block_value_summary(&[payment.clone(), double_spend]),
+                Err("utxo spent twice in block")
+            );
+
+            let mut forged = refund;
+            forged.inputs[0].signature = Signature::random();
+            assert_eq!(Utxo::block_value_summary(&[payment, forged]), Err("signature must be valid"));
+        });
+    }
+
+    #[test]
+    fn unsigned_spend_only_accepted_when_allowed() {
+        with_externalities(&mut new_test_ext(), || {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let unsigned = |parent_output, salt| Transaction {
+                inputs: vec![TransactionInput { parent_output, signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 50, pubkey: alice, salt, ..Default::default() }],
+            };
+
+            assert!(!Utxo::allow_unsigned_spend());
+            assert_err!(Utxo::execute(Origin::INHERENT, unsigned(alice_utxo_100().0, 2)), "signature must be valid");
+
+            <AllowUnsignedSpend<Test>>::put(true);
+            let first = unsigned(alice_utxo_100().0, 2);
+            assert_ok!(Utxo::execute(Origin::INHERENT, first.clone()));
+
+            // The placeholder signature is not subject to replay protection
+            let change = Utxo::output_hash(&first.outputs[0]);
+            assert_ok!(Utxo::execute(Origin::INHERENT, unsigned(change, 3)));
+        });
+    }
+
+    #[test]
+    fn diff_snapshots_lists_added_and_removed_utxos() {
+        let utxo = |salt| {
+            let output = TransactionOutput { value: 1, pubkey: H256::from_slice(&ALICE_KEY), salt, ..Default::default() };
+            (BlakeTwo256::hash_of(&output), output)
+        };
+        let before = vec![utxo(1), utxo(2), utxo(3)];
+        let after = vec![utxo(4), utxo(2), utxo(5), utxo(3)];
+
+        let (added, removed) = Utxo::diff_snapshots(&before, &after);
+        let mut expected_added = vec![utxo(4).0, utxo(5).0];
+        expected_added.sort();
+        assert_eq!(added, expected_added);
+        assert_eq!(removed, vec![utxo(1).0]);
+
+        assert_eq!(Utxo::diff_snapshots(&before, &before), (vec![], vec![]));
+        assert_eq!(Utxo::diff_snapshots(&[], &before[..1]), (vec![utxo(1).0], vec![]));
+    }
+
+    #[test]
+    fn is_authority_matches_consensus_authorities() {
+        with_externalities(&mut new_test_ext(), || {
+            let authority = ed25519::Public([7; 32]);
+            Consensus::set_authorities(&[authority]);
+
+            assert!(Utxo::is_authority(&BlakeTwo256::hash(&[7; 32])));
+            assert!(!Utxo::is_authority(&H256::from([7; 32])));
+            assert!(!Utxo::is_authority(&H256::random()));
+        });
+    }
+
+    #[test]
+    fn relock_extends_time_lock_on_a_new_output() {
+        with_externalities(&mut new_test_ext(), || {
+            let (hash, output) = alice_utxo_100();
+            let input = |parent_output: H256, new_until: u64| TransactionInput {
+                parent_output,
+                signature: Signature::from_slice(&alice_pair().sign(&Utxo::relock_payload(&parent_output, new_until)).0),
+            };
+
+            // Before the deadline
+            run_to_block(1);
+            assert_ok!(Utxo::lock_utxo(&hash, Some(5)));
+            assert_err!(Utxo::relock(Origin::signed(1), input(hash, 1), 1), "block number is in the past");
+            let mut forged = input(hash, 8);
+            forged.signature = Signature::random();
+            assert_err!(Utxo::relock(Origin::signed(1), forged, 8), "signature must be valid");
+
+            // Neither a spend signature nor one for another deadline will do
+            let spend = TransactionInput { parent_output: hash, signature: Signature::from_slice(&ALICE_SIG100) };
+            assert_err!(Utxo::relock(Origin::signed(1), spend, 8), "signature must be valid");
+            assert_err!(Utxo::relock(Origin::signed(1), input(hash, 8), 9), "signature must be valid");
+
+            assert_ok!(Utxo::relock(Origin::signed(1), input(hash, 8), 8));
+            let relocked = TransactionOutput { salt: 1, ..output.clone() };
+            let relocked_hash = Utxo::output_hash(&relocked);
+            assert!(!<UnspentOutputs<Test>>::exists(hash));
+            assert!(<LockedOutputs<Test>>::get(&hash).is_none());
+            assert_eq!(<UnspentOutputs<Test>>::get(&relocked_hash), Some(relocked));
+            assert_eq!(<LockedOutputs<Test>>::get(&relocked_hash), Some(LockStatus::LockedUntil(8)));
+            assert!(utxo_events().contains(&Event::OutputRelocked(hash, relocked_hash)));
+
+            // After the deadline
+            run_to_block(9);
+            assert_ok!(Utxo::relock(Origin::signed(1), input(relocked_hash, 20), 20));
+            let rolled = Utxo::output_hash(&TransactionOutput { salt: 9, ..output });
+            assert_eq!(<LockedOutputs<Test>>::get(&rolled), Some(LockStatus::LockedUntil(20)));
+            assert_eq!(Utxo::utxo_count(), 2);
+
+            // Indefinite locks are not time-locks
+            assert_ok!(Utxo::lock_utxo(&alice_utxo().0, None));
+            assert_err!(Utxo::relock(Origin::signed(1), input(alice_utxo().0, 30), 30), "utxo is not time-locked");
+        });
+    }
+
+    #[test]
+    fn relock_keeps_age_of_spent_output() {
+        with_externalities(&mut new_test_ext(), || {
+            let input = |parent_output: H256, new_until: u64| TransactionInput {
+                parent_output,
+                signature: Signature::from_slice(&alice_pair().sign(&Utxo::relock_payload(&parent_output, new_until)).0),
+            };
+            let decaying = TransactionOutput {
+                value: 100,
+                pubkey: H256::from_slice(&ALICE_KEY),
+                condition: SpendCondition::Demurrage { rate_bps: 1_000, period: 10 },
+                ..Default::default()
+            };
+            let hash = Utxo::output_hash(&decaying);
+
+            run_to_block(1);
+            Utxo::insert_utxo(hash, decaying.clone());
+            run_to_block(21);
+            assert_eq!(Utxo::effective_value(&hash, 21), Some(80));
+
+            // Demurrage goes on from the creation of the spent output
+            assert_ok!(Utxo::relock(Origin::signed(1), input(hash, 25), 25));
+            let relocked = Utxo::output_hash(&TransactionOutput { salt: 21, ..decaying });
+            assert_eq!(Utxo::created_at(&relocked), 1);
+            assert_eq!(Utxo::effective_value(&relocked, 31), Some(70));
+
+            // Genesis outputs stay of unknown age
+            let (genesis, _) = alice_utxo_100();
+            assert_ok!(Utxo::relock(Origin::signed(1), input(genesis, 25), 25));
+            let relocked = Utxo::output_hash(&TransactionOutput { salt: 21, ..alice_utxo_100().1 });
+            assert!(<UnspentOutputs<Test>>::exists(relocked));
+            assert!(!<CreatedAt<Test>>::exists(relocked));
+            assert_indexes_consistent();
+        });
+    }
+
+    #[test]
+    fn relock_respects_reservations() {
+        with_externalities(&mut new_test_ext(), || {
+            let (hash, _) = alice_utxo_100();
+            let input = TransactionInput {
+                parent_output: hash,
+                signature: Signature::from_slice(&alice_pair().sign(&Utxo::relock_payload(&hash, 8)).0),
+            };
+            <ReservedOutputs<Test>>::insert(&hash, true);
+
+            assert_err!(Utxo::relock(Origin::signed(1), input.clone(), 8), "utxo is reserved");
+            assert!(<UnspentOutputs<Test>>::exists(hash));
+
+            <RejectReserved<Test>>::put(false);
+            assert_ok!(Utxo::relock(Origin::signed(1), input, 8));
+            assert!(!<UnspentOutputs<Test>>::exists(hash));
+        });
+    }
+
+    #[test]
+    fn debug_dump_lists_leftover_count_and_locks() {
+        with_externalities(&mut new_test_ext(), || {
+            let (hash, _) = alice_utxo_100();
+            assert_ok!(Utxo::lock_utxo(&hash, Some(5)));
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 7);
+
+            let dump = Utxo::debug_dump();
+            assert!(dump.contains("leftover asset=0 value=7\n"));
+            assert!(dump.contains("utxo_count=2\n"));
+            assert!(dump.contains(&format!("utxo {:?} value=100 ", hash)));
+            assert!(dump.contains("lock=Some(LockedUntil(5))"));
+            assert!(dump.contains("lock=None"));
+        });
+    }
+
+    #[test]
+    fn effective_value_accounts_for_locks_and_decay() {
+        with_externalities(&mut new_test_ext(), || {
+            let (locked, _) = alice_utxo();
+            let (free, _) = alice_utxo_100();
+            assert_ok!(Utxo::lock_utxo(&locked, Some(10)));
+
+            let decaying = TransactionOutput {
+                value: 1_000,
+                pubkey: H256::from_slice(&ALICE_KEY),
+                salt: 3,
+                condition: SpendCondition::Demurrage { rate_bps: 100, period: 10 },
+                asset_id: NATIVE_ASSET,
+                expires_at: None,
+            };
+            let decaying_hash = Utxo::output_hash(&decaying);
+            Utxo::insert_utxo(decaying_hash, decaying);
+
+            assert_eq!(Utxo::effective_value(&locked, 9), Some(0));
+            assert_eq!(Utxo::effective_value(&locked, 10), Some(Value::max_value()));
+            assert_eq!(Utxo::effective_value(&free, 9), Some(100));
+            assert_eq!(Utxo::effective_value(&decaying_hash, 9), Some(1_000));
+            assert_eq!(Utxo::effective_value(&decaying_hash, 25), Some(980));
+            assert_eq!(Utxo::effective_value(&H256::random(), 9), None);
+        });
+    }
+
+    #[test]
+    fn leftover_shares_salted_by_custom_provider() {
+        with_externalities(&mut new_test_ext(), || {
+            NEXT_LEFTOVER_SALT.with(|next| *next.borrow_mut() = Some(100));
+            let authorities = vec![H256::random(), H256::random(), H256::random()];
+
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 9);
+            Utxo::spend_leftover(&authorities);
+
+            for (authority, salt) in authorities.iter().zip(100..) {
+                let share = TransactionOutput { value: 3, pubkey: *authority, salt, ..Default::default() };
+                assert!(<UnspentOutputs<Test>>::exists(Utxo::output_hash(&share)));
+            }
+            assert_eq!(NEXT_LEFTOVER_SALT.with(|next| *next.borrow()), Some(103));
+        });
+    }
+
+    #[test]
+    fn error_codes_are_stable_and_unique() {
+        assert_eq!(Utxo::error_code("no inputs"), 1);
+        assert_eq!(Utxo::error_code("signature must be valid"), 12);
+        assert_eq!(Utxo::error_code("output already exists"), 16);
+        assert_eq!(Utxo::error_code("utxo is locked"), 7);
+        assert_eq!(Utxo::error_code("no rewards to claim"), 47);
+        assert_eq!(Utxo::error_code("bad origin: expected to be an inherent origin"), UNKNOWN_ERROR_CODE);
+
+        // Input failures are reported through their messages
+        for failure in [InputFailure::Missing, InputFailure::Locked, InputFailure::Reserved, InputFailure::Frozen,
+            InputFailure::ReplayedSignature, InputFailure::MixedOwner, InputFailure::BadSignature].iter() {
+            assert_ne!(Utxo::error_code(failure.message()), UNKNOWN_ERROR_CODE);
+        }
 
-        (BlakeTwo256::hash_of(&transaction), transaction)
+        let mut codes: Vec<_> = ERROR_CODES.iter().map(|(_, code)| *code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), ERROR_CODES.len());
+        assert!(!codes.contains(&UNKNOWN_ERROR_CODE));
+        for (message, code) in ERROR_CODES.iter() {
+            assert_eq!(Utxo::error_code(*message), *code);
+        }
     }
 
-    // This function basically just builds a genesis storage key/value store according to
-    // our desired mockup.
-    fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
-        let mut t = system::GenesisConfig::<Test>::default()
-            .build_storage()
-            .unwrap()
-            .0;
-        t.extend(
-            GenesisConfig::<Test> {
-                initial_utxo: vec![alice_utxo().1, alice_utxo_100().1],
-                ..Default::default()
+    /// Error literals the module code returns through `ensure!`, `Err`, `ok_or` and `reject`
+    fn returned_error_literals() -> Vec<&'static str> {
+        let source = include_str!("utxo.rs");
+        let source = &source[..source.find("#[cfg(test)]\nmod tests").unwrap()];
+
+        let mut literals = Vec::new();
+        for opener in ["ensure!(", "Err(", "ok_or(", "reject("].iter() {
+            for (start, _) in source.match_indices(opener) {
+                // Arguments up to the matching closing parenthesis
+                let rest = &source[start + opener.len()..];
+                let mut depth = 1;
+                let (end, _) = rest
+                    .char_indices()
+                    .find(|&(_, c)| {
+                        match c {
+                            '(' => depth += 1,
+                            ')' => depth -= 1,
+                            _ => {}
+                        }
+                        depth == 0
+                    })
+                    .unwrap();
+                // Unwrapped, so never returned
+                if rest[end + 1..].trim_start().starts_with(".unwrap()") {
+                    continue;
+                }
+                let args = rest[..end].trim_end().trim_end_matches(',').trim_end();
+                if args.ends_with('"') {
+                    let open = args[..args.len() - 1].rfind('"').unwrap();
+                    literals.push(&args[open + 1..args.len() - 1]);
+                }
             }
-            .build_storage()
-            .unwrap()
-            .0,
-        );
-        t.into()
+        }
+        literals
     }
 
-    // Exercise 1: Fortify transactions against attacks
-    // ================================================
-    //
-    // The following tests simulate malicious UTXO transactions
-    // Implement the check_transaction() function to thwart such attacks
-    //
-    // Hint: Examine types CheckResult, CheckInfo for the expected behaviors of this function
-    // Hint: Make this function public, as it will be later used outside of this module
+    #[test]
+    fn every_returned_error_has_a_code() {
+        let literals = returned_error_literals();
+        assert!(literals.contains(&"genesis utxo count mismatch"));
+        assert!(literals.contains(&"no such pending transaction"));
+        for literal in literals {
+            assert_ne!(Utxo::error_code(literal), UNKNOWN_ERROR_CODE, "{} has no error code", literal);
+        }
+    }
 
     #[test]
-    fn attack_with_empty_transactions() {
+    fn block_conservation_accounts_for_distributed_leftover() {
         with_externalities(&mut new_test_ext(), || {
-            assert_err!(
-                Utxo::execute(Origin::INHERENT, Transaction::default()), // an empty trx
-                "no inputs"
-            );
+            let alice = H256::from_slice(&ALICE_KEY);
+            let bob = H256::from_slice(bob_pair().public().as_ref());
+            let mut payment = Transaction {
+                inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                outputs: vec![
+                    TransactionOutput { value: 60, pubkey: bob, salt: 2, ..Default::default() },
+                    TransactionOutput { value: 35, pubkey: alice, salt: 2, ..Default::default() },
+                ],
+            };
+            sign_inputs(&mut payment, &alice_pair());
+            let block = vec![payment];
 
-            assert_err!(
-                Utxo::execute(
-                    Origin::INHERENT,
-                    Transaction {
-                        inputs: vec![TransactionInput::default()], // an empty trx
-                        outputs: vec![],
-                    }
-                ),
-                "no outputs"
-            );
+            assert_ok!(Utxo::verify_block_conservation(&block, 5));
+            assert_ok!(Utxo::verify_block_conservation(&[], 0));
+
+            // Paying out more leftover than the block left behind creates value
+            assert_err!(Utxo::verify_block_conservation(&block, 6), "value not conserved in block");
+            assert_err!(Utxo::verify_block_conservation(&block, 4), "value not conserved in block");
+            assert_err!(Utxo::verify_block_conservation(&[], Value::max_value()), "value not conserved in block");
         });
     }
 
     #[test]
-    fn attack_by_double_counting_input() {
+    fn oracle_output_spendable_only_when_condition_met() {
         with_externalities(&mut new_test_ext(), || {
-            let (parent_hash, _) = alice_utxo();
+            let alice = H256::from_slice(&ALICE_KEY);
+            let key = H256::random();
+            let conditional = TransactionOutput {
+                value: 100,
+                pubkey: alice,
+                salt: 3,
+                condition: SpendCondition::Oracle { key, expected: 42 },
+                asset_id: NATIVE_ASSET,
+                expires_at: None,
+            };
+            let hash = Utxo::output_hash(&conditional);
+            Utxo::insert_utxo(hash, conditional);
 
-            println!("PARENT HASH: {:x?}: ", parent_hash);
-            let transaction = Transaction {
-                inputs: vec![
-                    TransactionInput {
-                        parent_output: parent_hash,
-                        signature: Signature::from_slice(&ALICE_SIG),
-                    },
-                    TransactionInput {
-                        parent_output: parent_hash, // Double spending input!
-                        signature: Signature::from_slice(&ALICE_SIG),
-                    },
-                ],
-                outputs: vec![TransactionOutput {
-                    value: 100,
-                    pubkey: H256::from_slice(&ALICE_KEY),
-                    salt: 0,
-                }],
+            let mut transaction = Transaction {
+                inputs: vec![TransactionInput { parent_output: hash, signature: Signature::default() }],
+                outputs: vec![TransactionOutput { value: 100, pubkey: alice, salt: 4, ..Default::default() }],
             };
+            sign_inputs(&mut transaction, &alice_pair());
 
-            assert_err!(
-                Utxo::execute(Origin::INHERENT, transaction),
-                "each input must only be used once"
-            );
+            // Nothing reported yet, then another value
+            assert_err!(Utxo::execute(Origin::INHERENT, transaction.clone()), "signature must be valid");
+            assert_err!(Utxo::set_oracle_value(Origin::signed(1), key, Some(42)), "bad origin: expected to be a root origin");
+            assert_ok!(Utxo::set_oracle_value(Origin::ROOT, key, Some(41)));
+            assert_err!(Utxo::execute(Origin::INHERENT, transaction.clone()), "signature must be valid");
+
+            assert_ok!(Utxo::set_oracle_value(Origin::ROOT, key, Some(42)));
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction));
+            assert!(!<UnspentOutputs<Test>>::exists(hash));
         });
     }
 
     #[test]
-    fn attack_by_double_generating_output() {
+    fn verify_many_checks_each_transaction_on_its_own() {
         with_externalities(&mut new_test_ext(), || {
-            let (parent_hash, _) = alice_utxo();
+            let valid = alice_spend(alice_utxo_100().0, 90, 2);
+            let mut forged = alice_spend(alice_utxo().0, 90, 2);
+            forged.inputs[0].signature = Signature::random();
+            let child = alice_spend(Utxo::output_hash(&valid.outputs[0]), 90, 3);
+            let conflicting = alice_spend(alice_utxo_100().0, 80, 2);
 
-            let transaction = Transaction {
-                inputs: vec![TransactionInput {
-                    parent_output: parent_hash,
-                    signature: Signature::from_slice(&ALICE_SIG),
-                }],
-                outputs: vec![
-                    TransactionOutput {
-                        value: 100,
-                        pubkey: H256::from_slice(&ALICE_KEY),
-                        salt: 0,
-                    },
-                    TransactionOutput {
-                        // Same output defined here!
-                        value: 100,
-                        pubkey: H256::from_slice(&ALICE_KEY),
-                        salt: 0,
-                    },
-                ],
-            };
+            let results = Utxo::verify_many(&[valid.clone(), forged, child, conflicting]);
+            assert_eq!(results.len(), 4);
+            match results[0] {
+                Ok(CheckInfo::Totals { input: 100, output: 90, .. }) => {}
+                _ => panic!("valid transaction rejected"),
+            }
+            assert_eq!(results[1].as_ref().err(), Some(&"signature must be valid"));
+            match results[2] {
+                Ok(CheckInfo::MissingInputs(ref missing)) => assert_eq!(missing.len(), 1),
+                _ => panic!("child transaction not waiting for its parent"),
+            }
+            assert!(results[3].is_ok());
 
-            assert_err!(
-                Utxo::execute(Origin::INHERENT, transaction),
-                "each output must be defined only once"
-            );
+            // Nothing was applied
+            assert!(<UnspentOutputs<Test>>::exists(alice_utxo_100().0));
         });
     }
 
     #[test]
-    fn attack_with_invalid_signature() {
+    fn leftover_paid_to_converted_authority_keys() {
         with_externalities(&mut new_test_ext(), || {
-            let (parent_hash, _) = alice_utxo();
+            assert_eq!(AuthorityKeyAsPubkey::convert(ed25519::Public([7; 32])), H256::from([7; 32]));
 
-            let transaction = Transaction {
-                inputs: vec![TransactionInput {
-                    parent_output: parent_hash,
-                    signature: H512::random(), // Just a random signature!
-                }],
-                outputs: vec![TransactionOutput {
-                    value: 100,
-                    pubkey: H256::from_slice(&ALICE_KEY),
-                    salt: 0,
-                }],
-            };
+            Consensus::set_authorities(&[ed25519::Public([7; 32])]);
+            let pubkey = BlakeTwo256::hash(&[7; 32]);
+            <LeftoverTotals<Test>>::insert(NATIVE_ASSET, 10);
+            Utxo::on_finalize(1);
 
-            assert_err!(
-                Utxo::execute(Origin::INHERENT, transaction),
-                "signature must be valid"
-            );
+            assert_eq!(Utxo::rewards_of(&pubkey), 10);
+            assert_eq!(Utxo::owned_outputs(&pubkey).len(), 1);
+            assert!(Utxo::owned_outputs(&H256::from([7; 32])).is_empty());
         });
     }
 
     #[test]
-    fn attack_by_permanently_sinking_outputs() {
+    fn expired_output_swept_into_leftover_at_its_deadline() {
         with_externalities(&mut new_test_ext(), || {
-            let (parent_hash, _) = alice_utxo();
-
-            let transaction = Transaction {
-                inputs: vec![TransactionInput {
-                    parent_output: parent_hash,
-                    signature: Signature::from_slice(&ALICE_SIG),
-                }],
-                outputs: vec![TransactionOutput {
-                    value: 0, // A 0 value output burns this output forever!
-                    pubkey: H256::from_slice(&ALICE_KEY),
-                    salt: 0,
-                }],
+            let alice = H256::from_slice(&ALICE_KEY);
+            let expiring = |expires_at| {
+                let mut transaction = Transaction {
+                    inputs: vec![TransactionInput { parent_output: alice_utxo_100().0, signature: Signature::default() }],
+                    outputs: vec![TransactionOutput { value: 100, pubkey: alice, salt: 2, expires_at, ..Default::default() }],
+                };
+                sign_inputs(&mut transaction, &alice_pair());
+                transaction
             };
 
-            assert_err!(
-                Utxo::execute(Origin::INHERENT, transaction),
-                "output value must be nonzero"
-            );
+            run_to_block(1);
+            assert_err!(Utxo::execute(Origin::INHERENT, expiring(Some(1))), "output expiry must be in the future");
+            let transaction = expiring(Some(3));
+            let hash = Utxo::output_hash(&transaction.outputs[0]);
+            assert_ok!(Utxo::execute(Origin::INHERENT, transaction));
+
+            run_to_block(2);
+            Utxo::on_finalize(2);
+            assert!(<UnspentOutputs<Test>>::exists(hash));
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 0);
+
+            run_to_block(3);
+            Utxo::on_finalize(3);
+            assert!(!<UnspentOutputs<Test>>::exists(hash));
+            assert_eq!(Utxo::leftover_total(NATIVE_ASSET), 100);
+            assert!(utxo_events().contains(&Event::OutputExpired(hash, 100)));
+            assert!(Utxo::owned_outputs(alice).iter().all(|owned| *owned != hash));
         });
     }
 
     #[test]
-    fn attack_by_overflowing() {
+    fn self_transfers_told_apart_from_payments() {
         with_externalities(&mut new_test_ext(), || {
-            let (parent_hash, _) = alice_utxo();
+            let bob = H256::from_slice(bob_pair().public().as_ref());
 
-            let transaction = Transaction {
-                inputs: vec![TransactionInput {
-                    parent_output: parent_hash,
-                    signature: Signature::from_slice(&ALICE_SIG),
-                }],
-                outputs: vec![
-                    TransactionOutput {
-                        value: Value::max_value(),
-                        pubkey: H256::from_slice(&ALICE_KEY),
-                        salt: 1,
-                    },
-                    TransactionOutput {
-                        value: 10 as Value, // Attempts to do overflow total output value
-                        pubkey: H256::from_slice(&ALICE_KEY),
-                        salt: 1,
-                    },
-                ],
-            };
+            let transaction = alice_spend(alice_utxo_100().0, 90, 2);
+            assert!(Utxo::is_self_transfer(&transaction));
 
-            assert_err!(
-                Utxo::execute(Origin::INHERENT, transaction),
-                "output value overflow"
-            );
+            let mut payment = transaction.clone();
+            payment.outputs[0].pubkey = bob;
+            assert!(!Utxo::is_self_transfer(&payment));
+
+            let mut mixed = transaction.clone();
+            mixed.outputs.push(TransactionOutput { value: 5, pubkey: bob, salt: 3, ..Default::default() });
+            assert!(!Utxo::is_self_transfer(&mixed));
+
+            // Inputs which can't be resolved say nothing about their owner
+            let pending = alice_spend(Utxo::output_hash(&transaction.outputs[0]), 90, 3);
+            assert!(!Utxo::is_self_transfer(&pending));
         });
     }
 
     #[test]
-    fn attack_by_over_spending() {
+    fn time_locked_utxo_spendable_once_lock_purged() {
         with_externalities(&mut new_test_ext(), || {
-            let (parent_hash, _) = alice_utxo_100();
+            let (hash, _) = alice_utxo_100();
+            run_to_block(1);
+            assert_ok!(Utxo::lock_utxo(&hash, Some(5)));
 
-            let transaction = Transaction {
-                inputs: vec![TransactionInput {
-                    parent_output: parent_hash,
-                    signature: Signature::from_slice(&ALICE_SIG100),
-                }],
-                outputs: vec![
-                    TransactionOutput {
-                        value: 100 as Value,
-                        pubkey: H256::from_slice(&ALICE_KEY),
-                        salt: 1,
-                    },
-                    TransactionOutput {
-                        value: 1 as Value, // Creates 1 new utxo out of thin air!
-                        pubkey: H256::from_slice(&ALICE_KEY),
-                        salt: 1,
-                    },
-                ],
-            };
+            // Too early
+            run_to_block(3);
+            assert_err!(Utxo::execute(Origin::INHERENT, alice_spend(hash, 90, 2)), "utxo is locked");
+            assert!(<UnspentOutputs<Test>>::exists(hash));
 
-            assert_err!(
-                Utxo::execute(Origin::INHERENT, transaction),
-                "output value must not exceed input value"
-            );
+            run_to_block(4);
+            assert!(<LockedOutputs<Test>>::exists(hash));
+
+            // Released as the deadline block starts
+            run_to_block(5);
+            assert!(!<LockedOutputs<Test>>::exists(hash));
+            assert!(utxo_events().contains(&Event::OutputUnlocked(hash)));
+
+            run_to_block(6);
+            assert_ok!(Utxo::execute(Origin::INHERENT, alice_spend(hash, 90, 2)));
+            assert!(!<UnspentOutputs<Test>>::exists(hash));
+            assert!(!<LockedOutputs<Test>>::exists(hash));
+            assert!(!<LockExpiry<Test>>::exists(5));
         });
     }
-    
+
     #[test]
-    fn valid_transaction() {
+    fn addresses_round_trip_and_catch_typos() {
+        let alice = H256::from_slice(&ALICE_KEY);
+        let address = Utxo::pubkey_to_address(&alice);
+        assert!(address.starts_with("utxo1"));
+        assert_eq!(Utxo::address_to_pubkey(&address), Ok(alice));
+        assert_eq!(Utxo::address_to_pubkey(&address.to_uppercase()), Ok(alice));
+        assert_eq!(Utxo::address_to_pubkey(&Utxo::pubkey_to_address(&H256::zero())), Ok(H256::zero()));
+
+        // A single mistyped character breaks the checksum
+        let mut typo = address.clone().into_bytes();
+        let last = typo.len() - 1;
+        typo[last] = if typo[last] == b'q' { b'p' } else { b'q' };
+        assert_eq!(Utxo::address_to_pubkey(&String::from_utf8(typo).unwrap()), Err("address checksum mismatch"));
+
+        assert_eq!(Utxo::address_to_pubkey(&address.replacen("utxo", "utxx", 1)), Err("invalid address"));
+        assert_eq!(Utxo::address_to_pubkey("utxo1b"), Err("invalid address"));
+    }
+
+    #[test]
+    fn lock_past_its_deadline_ignored_before_purge() {
         with_externalities(&mut new_test_ext(), || {
-            let (parent_hash, _) = alice_utxo();
+            let (hash, _) = alice_utxo_100();
+            // Not scheduled in `LockExpiry`, as locks placed before it existed
+            <LockedOutputs<Test>>::insert(hash, LockStatus::LockedUntil(5));
 
-            let transaction = Transaction {
-                inputs: vec![TransactionInput {
-                    parent_output: parent_hash,
-                    signature: Signature::from_slice(&ALICE_SIG),
-                }],
-                outputs: vec![TransactionOutput {
-                    value: 100,
-                    pubkey: H256::from_slice(&ALICE_KEY),
-                    salt: 2,
-                }],
-            };
-            
-            let output_hash = BlakeTwo256::hash_of(&transaction.outputs[0]);
+            run_to_block(4);
+            assert_err!(Utxo::check_transaction(&alice_spend(hash, 90, 2)), "utxo is locked");
 
-            assert_ok!(Utxo::execute(Origin::INHERENT, transaction));
-            assert!(!<UnspentOutputs<Test>>::exists(parent_hash));
-            assert!(<UnspentOutputs<Test>>::exists(output_hash));
+            run_to_block(6);
+            assert_eq!(<LockedOutputs<Test>>::get(&hash), Some(LockStatus::LockedUntil(5)));
+            assert_ok!(Utxo::execute(Origin::INHERENT, alice_spend(hash, 90, 2)));
+            assert!(!<UnspentOutputs<Test>>::exists(hash));
+            assert!(!<LockedOutputs<Test>>::exists(hash));
         });
     }
-}
\ No newline at end of file
+
+    /// Benchmarks used to calibrate the weight of transaction dispatch.
+    ///
+    /// Signature verification dominates, so cost is measured against the number
+    /// of inputs and outputs. Run with
+    /// `cargo +nightly bench -p utxo-runtime --features runtime-benchmarks`.
+    #[cfg(feature = "runtime-benchmarks")]
+    mod benchmarks {
+        use super::*;
+        use test::Bencher;
+
+        // Value of the n-th UTXO minted for a benchmark, large enough to split
+        fn minted_value(n: usize) -> Value {
+            1_000_000 + n as Value
+        }
+
+        // Mints `count` UTXOs for Alice, returning the inputs spending them
+        fn mint_inputs(count: usize) -> Vec<TransactionInput> {
+            let alice = H256::from_slice(&ALICE_KEY);
+            (0..count)
+                .map(|n| {
+                    assert_ok!(Utxo::mint(Origin::signed(1), minted_value(n), alice));
+                    let minted = TransactionOutput { value: minted_value(n), pubkey: alice, salt: 0, ..Default::default() };
+                    TransactionInput { parent_output: BlakeTwo256::hash_of(&minted), signature: Signature::default() }
+                })
+                .collect()
+        }
+
+        // Builds a signed transaction spending `inputs` UTXOs into `outputs` equal outputs
+        fn setup(inputs: usize, outputs: usize) -> Transaction {
+            let alice = H256::from_slice(&ALICE_KEY);
+            let total: Value = (0..inputs).map(minted_value).sum();
+            let mut transaction = Transaction {
+                inputs: mint_inputs(inputs),
+                outputs: (0..outputs)
+                    .map(|n| TransactionOutput {
+                        value: total / outputs as Value,
+                        pubkey: alice,
+                        salt: 1 + n as u64,
+                        ..Default::default()
+                    })
+                    .collect(),
+            };
+            sign_inputs(&mut transaction, &alice_pair());
+            transaction
+        }
+
+        fn bench_check_transaction(b: &mut Bencher, inputs: usize, outputs: usize) {
+            with_externalities(&mut new_test_ext(), || {
+                let transaction = setup(inputs, outputs);
+                b.iter(|| Utxo::check_transaction(&transaction).is_ok());
+            });
+        }
+
+        // Finalizes the current block, forgetting expired signatures, and starts
+        // the next one, clearing events and other per-block state, so that it
+        // doesn't pile up over iterations
+        fn next_block() {
+            let now = <system::Module<Test>>::block_number();
+            Utxo::on_finalize(now);
+            let next = now + 1;
+            <system::Module<Test>>::initialize(&next, &Default::default(), &Default::default());
+            Utxo::on_initialize(next);
+        }
+
+        // Executes a transaction and its mirror image, which restores the initial
+        // UTXO set, in a block of their own, so that each iteration starts from
+        // the same state.
+        //
+        // Replay protection stays as configured: iterations cycle through round
+        // trips moving the outputs under different salts, one more than the blocks
+        // of `SignatureWindow`, so that a round trip comes back only once its
+        // signatures have been forgotten.
+        fn bench_execute_round_trip(b: &mut Bencher, inputs: usize) {
+            with_externalities(&mut new_test_ext(), || {
+                let alice = H256::from_slice(&ALICE_KEY);
+                let spent = mint_inputs(inputs);
+                let round_trips: Vec<_> = (1..=Utxo::signature_window() + 1)
+                    .map(|salt| {
+                        let moved: Vec<_> = (0..inputs)
+                            .map(|n| TransactionOutput { value: minted_value(n), pubkey: alice, salt, ..Default::default() })
+                            .collect();
+                        let mut forth = Transaction {
+                            inputs: spent.clone(),
+                            outputs: moved.clone(),
+                        };
+                        let mut back = Transaction {
+                            inputs: moved
+                                .iter()
+                                .map(|output| TransactionInput { parent_output: BlakeTwo256::hash_of(output), signature: Signature::default() })
+                                .collect(),
+                            outputs: moved.iter().cloned().map(|output| TransactionOutput { salt: 0, ..output }).collect(),
+                        };
+                        sign_inputs(&mut forth, &alice_pair());
+                        sign_inputs(&mut back, &alice_pair());
+                        (forth, back)
+                    })
+                    .collect();
+
+                let mut round_trip = round_trips.iter().cycle();
+                let mut failures = 0;
+                b.iter(|| {
+                    next_block();
+                    let (forth, back) = round_trip.next().unwrap();
+                    if Utxo::execute(Origin::INHERENT, forth.clone()).is_err()
+                        || Utxo::execute(Origin::INHERENT, back.clone()).is_err()
+                    {
+                        failures += 1;
+                    }
+                });
+                assert_eq!(failures, 0);
+            });
+        }
+
+        #[bench]
+        fn check_transaction_1_input(b: &mut Bencher) {
+            bench_check_transaction(b, 1, 1);
+        }
+
+        #[bench]
+        fn check_transaction_10_inputs(b: &mut Bencher) {
+            bench_check_transaction(b, 10, 1);
+        }
+
+        #[bench]
+        fn check_transaction_100_inputs(b: &mut Bencher) {
+            bench_check_transaction(b, 100, 1);
+        }
+
+        #[bench]
+        fn check_transaction_10_outputs(b: &mut Bencher) {
+            bench_check_transaction(b, 1, 10);
+        }
+
+        #[bench]
+        fn check_transaction_100_outputs(b: &mut Bencher) {
+            bench_check_transaction(b, 1, 100);
+        }
+
+        // Parent hashes of a large transaction, all distinct
+        fn many_parents() -> Vec<H256> {
+            (0..1000).map(|_| H256::random()).collect()
+        }
+
+        #[bench]
+        fn duplicate_inputs_btree_1000(b: &mut Bencher) {
+            let parents = many_parents();
+            b.iter(|| {
+                let set: BTreeMap<_, ()> = parents.iter().map(|parent| (parent, ())).collect();
+                set.len() == parents.len()
+            });
+        }
+
+        #[bench]
+        fn duplicate_inputs_sorted_1000(b: &mut Bencher) {
+            let parents = many_parents();
+            b.iter(|| {
+                let mut sorted: Vec<_> = parents.iter().collect();
+                sorted.sort_unstable();
+                sorted.windows(2).all(|pair| pair[0] != pair[1])
+            });
+        }
+
+        #[bench]
+        fn execute_round_trip_1_input(b: &mut Bencher) {
+            bench_execute_round_trip(b, 1);
+        }
+
+        #[bench]
+        fn execute_round_trip_10_inputs(b: &mut Bencher) {
+            bench_execute_round_trip(b, 10);
+        }
+
+        #[bench]
+        fn execute_round_trip_100_inputs(b: &mut Bencher) {
+            bench_execute_round_trip(b, 100);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use primitives::{ed25519, sr25519, Pair};
+use primitives::{blake2_256, ed25519, sr25519, Pair};
 use substrate_service;
 use utxo_runtime::{
     AccountId, BalancesConfig, ConsensusConfig, GenesisConfig, IndicesConfig, SudoConfig,
@@ -39,6 +39,23 @@ fn account_key(s: &str) -> AccountId {
         .public()
 }
 
+/// Launch nonce of the chains below. Bump it whenever they are relaunched
+/// from a fresh genesis, so that signatures made for the previous launch
+/// can't be replayed on the new one.
+const LAUNCH_NONCE: u64 = 0;
+
+/// Launch hash of a chain: `blake2_256` of its id followed by its launch
+/// nonce, little endian.
+///
+/// Required by every chain spec. Spend signatures cover the launch hash, so
+/// chains sharing one, like those left with the all-zero default, accept
+/// each other's transactions.
+fn launch_hash(chain_id: &str, launch_nonce: u64) -> H256 {
+    let mut data = chain_id.as_bytes().to_vec();
+    data.extend_from_slice(&launch_nonce.to_le_bytes());
+    H256::from(blake2_256(&data))
+}
+
 impl Alternative {
     /// Get an actual chain config from one of the alternatives.
     pub(crate) fn load(self) -> Result<ChainSpec, String> {
@@ -51,6 +68,7 @@ impl Alternative {
                         vec![authority_key("Alice")],
                         vec![account_key("Alice")],
                         account_key("Alice"),
+                        launch_hash("dev", LAUNCH_NONCE),
                     )
                 },
                 vec![],
@@ -74,6 +92,7 @@ impl Alternative {
                             account_key("Ferdie"),
                         ],
                         account_key("Alice"),
+                        launch_hash("local_testnet", LAUNCH_NONCE),
                     )
                 },
                 vec![],
@@ -103,6 +122,7 @@ fn testnet_genesis(
     initial_authorities: Vec<AuthorityId>,
     endowed_accounts: Vec<AccountId>,
     root_key: AccountId,
+    launch_hash: H256,
 ) -> GenesisConfig {
     GenesisConfig {
 		consensus: Some(ConsensusConfig {
@@ -134,8 +154,12 @@ fn testnet_genesis(
 					value: utxo::Value::max_value(),
 					pubkey: H256::from_slice(&NICOLE),
 					salt: 0,
+					condition: utxo::SpendCondition::Unconditional,
+					asset_id: utxo::NATIVE_ASSET,
+					expires_at: None,
 				}
 			],
+            launch_hash,
             ..Default::default()
         }),
 	}